@@ -0,0 +1,263 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lending-specific expectations on the outcome of an instruction.
+//!
+//! Expectations are built with the `expect_*` functions and passed as a
+//! slice to [`crate::mollusk::LendingTestContext::execute_and_expect`], much
+//! like Mollusk's `Check`s. Unlike checks, every expectation is evaluated
+//! and all failures are reported together.
+
+use crate::{encoding, layout::Bank};
+use solana_account::Account;
+use solana_instruction_error::InstructionError;
+use solana_program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token_interface::state::Account as TokenAccount;
+use std::fmt;
+
+/// Errors raised by the Anchor framework, by name.
+///
+/// Programs' own `#[error_code]` errors start at 6000 and are expected with
+/// [`AnchorError::Custom`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum AnchorError {
+    InstructionMissing,
+    InstructionFallbackNotFound,
+    InstructionDidNotDeserialize,
+    ConstraintMut,
+    ConstraintHasOne,
+    ConstraintSigner,
+    ConstraintRaw,
+    ConstraintOwner,
+    ConstraintSeeds,
+    ConstraintAddress,
+    ConstraintTokenMint,
+    ConstraintTokenOwner,
+    ConstraintAssociated,
+    RequireViolated,
+    AccountDiscriminatorMismatch,
+    AccountDidNotDeserialize,
+    AccountNotEnoughKeys,
+    AccountNotMutable,
+    AccountOwnedByWrongProgram,
+    InvalidProgramId,
+    AccountNotSigner,
+    AccountNotInitialized,
+    DeclaredProgramIdMismatch,
+    /// A program-defined error code.
+    Custom(u32),
+}
+
+impl AnchorError {
+    /// Every named error, for lookup by name.
+    const NAMED: &[AnchorError] = &[
+        AnchorError::InstructionMissing,
+        AnchorError::InstructionFallbackNotFound,
+        AnchorError::InstructionDidNotDeserialize,
+        AnchorError::ConstraintMut,
+        AnchorError::ConstraintHasOne,
+        AnchorError::ConstraintSigner,
+        AnchorError::ConstraintRaw,
+        AnchorError::ConstraintOwner,
+        AnchorError::ConstraintSeeds,
+        AnchorError::ConstraintAddress,
+        AnchorError::ConstraintTokenMint,
+        AnchorError::ConstraintTokenOwner,
+        AnchorError::ConstraintAssociated,
+        AnchorError::RequireViolated,
+        AnchorError::AccountDiscriminatorMismatch,
+        AnchorError::AccountDidNotDeserialize,
+        AnchorError::AccountNotEnoughKeys,
+        AnchorError::AccountNotMutable,
+        AnchorError::AccountOwnedByWrongProgram,
+        AnchorError::InvalidProgramId,
+        AnchorError::AccountNotSigner,
+        AnchorError::AccountNotInitialized,
+        AnchorError::DeclaredProgramIdMismatch,
+    ];
+
+    /// Look up a named error, such as `ConstraintSeeds`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMED.iter().copied().find(|error| format!("{:?}", error) == name)
+    }
+
+    /// The error's code, as returned in `InstructionError::Custom`.
+    pub fn code(self) -> u32 {
+        match self {
+            AnchorError::InstructionMissing => 100,
+            AnchorError::InstructionFallbackNotFound => 101,
+            AnchorError::InstructionDidNotDeserialize => 102,
+            AnchorError::ConstraintMut => 2000,
+            AnchorError::ConstraintHasOne => 2001,
+            AnchorError::ConstraintSigner => 2002,
+            AnchorError::ConstraintRaw => 2003,
+            AnchorError::ConstraintOwner => 2004,
+            AnchorError::ConstraintSeeds => 2006,
+            AnchorError::ConstraintAssociated => 2009,
+            AnchorError::ConstraintAddress => 2012,
+            AnchorError::ConstraintTokenMint => 2014,
+            AnchorError::ConstraintTokenOwner => 2015,
+            AnchorError::RequireViolated => 2500,
+            AnchorError::AccountDiscriminatorMismatch => 3002,
+            AnchorError::AccountDidNotDeserialize => 3003,
+            AnchorError::AccountNotEnoughKeys => 3005,
+            AnchorError::AccountNotMutable => 3006,
+            AnchorError::AccountOwnedByWrongProgram => 3007,
+            AnchorError::InvalidProgramId => 3008,
+            AnchorError::AccountNotSigner => 3010,
+            AnchorError::AccountNotInitialized => 3012,
+            AnchorError::DeclaredProgramIdMismatch => 4100,
+            AnchorError::Custom(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnchorError::Custom(code) => write!(f, "custom error {}", code),
+            error => write!(f, "{:?} ({})", error, error.code()),
+        }
+    }
+}
+
+/// A single expectation on the outcome of an instruction.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum Expectation {
+    Success,
+    Failure,
+    Error(AnchorError),
+    TokenBalance { account: Pubkey, amount: u64 },
+    BankField { bank: Pubkey, field: String, value: u64 },
+    AccountClosed(Pubkey),
+}
+
+/// Expect the instruction to succeed.
+#[allow(dead_code)]
+pub fn expect_success() -> Expectation {
+    Expectation::Success
+}
+
+/// Expect the instruction to fail, with any error.
+#[allow(dead_code)]
+pub fn expect_failure() -> Expectation {
+    Expectation::Failure
+}
+
+/// Expect the instruction to fail with an Anchor or program error, as
+/// matched by the program's instruction encoding.
+#[allow(dead_code)]
+pub fn expect_error(error: AnchorError) -> Expectation {
+    Expectation::Error(error)
+}
+
+/// Expect an SPL Token account to hold `amount` tokens afterwards.
+#[allow(dead_code)]
+pub fn expect_token_balance(account: Pubkey, amount: u64) -> Expectation {
+    Expectation::TokenBalance { account, amount }
+}
+
+/// Expect a numeric field of a `Bank` account, such as `total_deposits`, to
+/// equal `value` afterwards.
+#[allow(dead_code)]
+pub fn expect_bank_field(bank: Pubkey, field: &str, value: u64) -> Expectation {
+    Expectation::BankField { bank, field: field.to_string(), value }
+}
+
+/// Expect an account to be closed, i.e. missing or drained of lamports,
+/// afterwards.
+#[allow(dead_code)]
+pub fn expect_account_closed(account: Pubkey) -> Expectation {
+    Expectation::AccountClosed(account)
+}
+
+impl Expectation {
+    /// Evaluate the expectation against an instruction's outcome.
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_result` - The instruction's result
+    /// * `account` - Looks up an account's state after the instruction
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the expectation holds
+    /// * `Err(String)` - What was expected and what was found
+    pub fn evaluate(
+        &self,
+        raw_result: &Result<(), InstructionError>,
+        account: impl Fn(&Pubkey) -> Option<Account>,
+    ) -> Result<(), String> {
+        match self {
+            Expectation::Success => match raw_result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(format!("expected success, got {:?}", err)),
+            },
+            Expectation::Failure => match raw_result {
+                Ok(()) => Err("expected failure, but the instruction succeeded".to_string()),
+                Err(_) => Ok(()),
+            },
+            Expectation::Error(error) => {
+                let encoding = encoding::current().map_err(|err| err.to_string())?;
+                match raw_result {
+                    Err(err) if encoding.matches_error(*error, err) => Ok(()),
+                    Err(err) => Err(format!("expected {}, got {:?}", error, err)),
+                    Ok(()) => Err(format!("expected {}, but the instruction succeeded", error)),
+                }
+            }
+            Expectation::TokenBalance { account: pubkey, amount } => {
+                let token = account(pubkey)
+                    .and_then(|account| TokenAccount::unpack(&account.data).ok())
+                    .ok_or_else(|| format!("{} is not an SPL token account", pubkey))?;
+                if token.amount == *amount {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "expected token account {} to hold {}, found {}",
+                        pubkey, amount, token.amount
+                    ))
+                }
+            }
+            Expectation::BankField { bank, field, value } => {
+                let state = account(bank)
+                    .and_then(|account| Bank::decode(&account.data))
+                    .ok_or_else(|| format!("{} is not a Bank account", bank))?;
+                let found = state
+                    .fields()
+                    .into_iter()
+                    .find(|(name, _)| *name == *field)
+                    .map(|(_, found)| found)
+                    .ok_or_else(|| format!("Bank has no field `{}`", field))?;
+                if found == value.to_string() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "expected Bank {}.{} to be {}, found {}",
+                        bank, field, value, found
+                    ))
+                }
+            }
+            Expectation::AccountClosed(pubkey) => match account(pubkey) {
+                Some(account) if account.lamports > 0 => Err(format!(
+                    "expected {} to be closed, it still holds {} lamports",
+                    pubkey, account.lamports
+                )),
+                _ => Ok(()),
+            },
+        }
+    }
+}
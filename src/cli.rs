@@ -0,0 +1,283 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local developer mode.
+//!
+//! On the platform the tester is configured through environment variables.
+//! Locally it can instead be given a repository and the stages or modules to
+//! run, e.g. `--repo ../my-lending --stage lc3 --module liquidation`; the
+//! test-case JSON is then built from [`definition::STAGES`].
+
+use crate::{definition, helpers::set_repo_dir};
+use std::{collections::HashMap, path::PathBuf, process::ExitCode};
+
+const USAGE: &str = "\
+Usage: solana-lending-program-tester [--repo <dir>] [--stage <slug>]... [--module <name>]...
+
+Runs the selected stages against the lending program in <dir>, or in the
+current directory if --repo is omitted. Without --stage or --module, every
+stage runs.
+
+Options:
+  --repo <dir>       The repository with the lending program
+  --stage <slug>     Run a stage, e.g. lc3; may be repeated
+  --module <name>    Run every stage of a module, e.g. liquidation; may be repeated
+  --list             List the stages and their modules
+  -h, --help         Show this help";
+
+/// Arguments of a local run.
+#[derive(Debug, Default)]
+struct Options {
+    repo: Option<PathBuf>,
+    stages: Vec<String>,
+    modules: Vec<String>,
+    list: bool,
+    help: bool,
+}
+
+/// Run the tester locally with command-line arguments.
+///
+/// # Arguments
+///
+/// * `args` - The arguments after the program name
+///
+/// # Returns
+///
+/// * `ExitCode` - The tester's exit code, or failure for invalid arguments
+pub fn run(args: &[String]) -> ExitCode {
+    let options = match parse(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if options.list {
+        for stage in definition::STAGES {
+            println!("{:<5} {:<18} {}", stage.slug, stage.module, stage.title);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let test_cases = match test_cases_json(&options.stages, &options.modules) {
+        Ok(test_cases) => test_cases,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    let repo_dir = match options.repo.map_or_else(std::env::current_dir, Ok) {
+        Ok(repo_dir) => std::path::absolute(&repo_dir).unwrap_or(repo_dir),
+        Err(err) => {
+            eprintln!("cannot determine the repository directory: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if !repo_dir.is_dir() {
+        eprintln!("{} is not a directory", repo_dir.display());
+        return ExitCode::FAILURE;
+    }
+
+    set_repo_dir(repo_dir.clone());
+    let mut env: HashMap<String, String> = std::env::vars().collect();
+    env.insert("STACKCLASS_REPOSITORY_DIR".to_string(), repo_dir.display().to_string());
+    env.insert("STACKCLASS_TEST_CASES_JSON".to_string(), test_cases);
+
+    tester::run(env, definition::build())
+}
+
+/// Parse the command-line arguments of a local run.
+///
+/// # Arguments
+///
+/// * `args` - The arguments after the program name
+///
+/// # Returns
+///
+/// * `Ok(Options)` - The parsed options
+/// * `Err(String)` - If an argument is unknown or lacks its value
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--repo" => options.repo = Some(PathBuf::from(value()?)),
+            "--stage" => options.stages.push(value()?),
+            "--module" => options.modules.push(value()?),
+            "--list" => options.list = true,
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("unknown argument `{}`", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Build the test-case JSON for the selected stages, in course order.
+///
+/// # Arguments
+///
+/// * `stages` - Stage slugs to run
+/// * `modules` - Modules whose stages to run
+///
+/// # Returns
+///
+/// * `Ok(String)` - The value for `STACKCLASS_TEST_CASES_JSON`
+/// * `Err(String)` - If a stage or module does not exist
+fn test_cases_json(stages: &[String], modules: &[String]) -> Result<String, String> {
+    if let Some(unknown) =
+        stages.iter().find(|slug| !definition::STAGES.iter().any(|stage| stage.slug == *slug))
+    {
+        return Err(format!("unknown stage `{}`; run with --list to see every stage", unknown));
+    }
+    if let Some(unknown) = modules
+        .iter()
+        .find(|module| !definition::STAGES.iter().any(|stage| stage.module == *module))
+    {
+        return Err(format!("unknown module `{}`; run with --list to see every module", unknown));
+    }
+
+    let select_all = stages.is_empty() && modules.is_empty();
+    let test_cases: Vec<serde_json::Value> = definition::STAGES
+        .iter()
+        .enumerate()
+        .filter(|(_, stage)| {
+            select_all ||
+                stages.iter().any(|slug| slug == stage.slug) ||
+                modules.iter().any(|module| module == stage.module)
+        })
+        .map(|(index, stage)| {
+            serde_json::json!({
+                "slug": stage.slug,
+                "tester_log_prefix": format!("tester::#{}", stage.slug),
+                "title": format!("Stage #{}: {}", index + 1, stage.title),
+            })
+        })
+        .collect();
+    serde_json::to_string(&test_cases).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn slugs(json: &str) -> Vec<String> {
+        let cases: Vec<Value> = serde_json::from_str(json).unwrap();
+        cases.iter().map(|case| case["slug"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options =
+            parse(&args(&["--repo", "../lending", "--stage", "lc3", "--module", "liquidation"]))
+                .unwrap();
+        assert_eq!(options.repo, Some(PathBuf::from("../lending")));
+        assert_eq!(options.stages, ["lc3"]);
+        assert_eq!(options.modules, ["liquidation"]);
+        assert!(!options.list && !options.help);
+
+        let options = parse(&args(&["--list", "-h"])).unwrap();
+        assert!(options.list && options.help);
+    }
+
+    #[test]
+    fn rejects_unknown_arguments() {
+        assert_eq!(parse(&args(&["--stages", "lc3"])).unwrap_err(), "unknown argument `--stages`");
+        assert_eq!(parse(&args(&["lc3"])).unwrap_err(), "unknown argument `lc3`");
+    }
+
+    #[test]
+    fn rejects_a_missing_value() {
+        for flag in ["--repo", "--stage", "--module"] {
+            assert_eq!(parse(&args(&[flag])).unwrap_err(), format!("{} needs a value", flag));
+        }
+    }
+
+    #[test]
+    fn repeated_flags_accumulate_or_override() {
+        let options = parse(&args(&[
+            "--stage",
+            "lc3",
+            "--stage",
+            "li2",
+            "--module",
+            "liquidation",
+            "--module",
+            "lending_core",
+            "--repo",
+            "first",
+            "--repo",
+            "second",
+            "--list",
+            "--list",
+        ]))
+        .unwrap();
+        assert_eq!(options.stages, ["lc3", "li2"]);
+        assert_eq!(options.modules, ["liquidation", "lending_core"]);
+        assert_eq!(options.repo, Some(PathBuf::from("second")));
+        assert!(options.list);
+    }
+
+    #[test]
+    fn selects_every_stage_by_default() {
+        let json = test_cases_json(&[], &[]).unwrap();
+        let all: Vec<String> =
+            definition::STAGES.iter().map(|stage| stage.slug.to_string()).collect();
+        assert_eq!(slugs(&json), all);
+    }
+
+    #[test]
+    fn selects_stages_and_modules_in_course_order() {
+        let json = test_cases_json(&args(&["li2", "lc3", "li2"]), &args(&["liquidation"])).unwrap();
+        let expected: Vec<String> = definition::STAGES
+            .iter()
+            .filter(|stage| stage.slug == "lc3" || stage.module == "liquidation")
+            .map(|stage| stage.slug.to_string())
+            .collect();
+        assert_eq!(slugs(&json), expected);
+    }
+
+    #[test]
+    fn describes_each_test_case() {
+        let json = test_cases_json(&args(&["lc3"]), &[]).unwrap();
+        let index = definition::STAGES.iter().position(|stage| stage.slug == "lc3").unwrap();
+        let title = definition::STAGES[index].title;
+        let cases: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            cases,
+            serde_json::json!([{
+                "slug": "lc3",
+                "tester_log_prefix": "tester::#lc3",
+                "title": format!("Stage #{}: {}", index + 1, title),
+            }])
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_stages_and_modules() {
+        assert!(test_cases_json(&args(&["zz9"]), &[]).unwrap_err().contains("unknown stage `zz9`"));
+        assert!(
+            test_cases_json(&[], &args(&["nope"])).unwrap_err().contains("unknown module `nope`")
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compute-unit accounting for the lending program.
+//!
+//! Every instruction a [`crate::mollusk::LendingTestContext`] executes is
+//! recorded here under the stage that is running. When a stage finishes, its
+//! usage is summarized in debug mode and checked against the optional
+//! per-instruction ceilings in [`COMPUTE_UNIT_LIMITS_ENV`].
+
+use crate::{
+    encoding,
+    helpers::{LENDING_INSTRUCTIONS, config_value},
+    mollusk::TestContextError,
+    report,
+};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+/// Environment variable holding per-instruction compute-unit ceilings, as
+/// comma-separated `name=units` pairs, e.g. `deposit=40000,withdraw=40000`.
+pub const COMPUTE_UNIT_LIMITS_ENV: &str = "STACKCLASS_COMPUTE_UNIT_LIMITS";
+
+/// The compute units consumed by one executed instruction.
+#[derive(Debug, Clone)]
+pub struct ComputeUsage {
+    /// The instruction name, or its discriminator in hex if unknown.
+    pub instruction: String,
+    /// The compute units consumed.
+    pub units: u64,
+}
+
+/// Usage recorded since the current stage started.
+static STAGE_USAGE: Mutex<Vec<ComputeUsage>> = Mutex::new(Vec::new());
+
+/// Total compute units of the last stage that finished.
+static LAST_STAGE_UNITS: AtomicU64 = AtomicU64::new(0);
+
+/// Record the compute units consumed by an instruction.
+///
+/// # Arguments
+///
+/// * `data` - The instruction data, used to name the instruction
+/// * `units` - The compute units consumed
+///
+/// # Returns
+///
+/// * `ComputeUsage` - The recorded usage
+pub fn record(data: &[u8], units: u64) -> ComputeUsage {
+    let usage = ComputeUsage { instruction: instruction_label(data), units };
+    STAGE_USAGE.lock().unwrap_or_else(|err| err.into_inner()).push(usage.clone());
+    usage
+}
+
+/// Name an instruction from its tag in the program's instruction encoding.
+fn instruction_label(data: &[u8]) -> String {
+    if let Ok(encoding) = encoding::current() &&
+        let Some(name) = LENDING_INSTRUCTIONS
+            .iter()
+            .find(|name| encoding.tag(name).is_some_and(|tag| data.starts_with(&tag)))
+    {
+        return name.to_string();
+    }
+    let Some(discriminator) = data.get(..8) else {
+        return format!("<{} bytes>", data.len());
+    };
+    discriminator.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Run a stage and check the compute units its instructions consumed.
+///
+/// The stage's own error takes precedence; a stage that passes fails if any
+/// instruction exceeded its ceiling. In debug mode a per-instruction summary
+/// is logged either way.
+///
+/// # Arguments
+///
+/// * `stage` - The stage slug
+/// * `run` - The stage body
+///
+/// # Returns
+///
+/// * `Ok(())` - If the stage passed within its compute budget
+/// * `Err(tester::CaseError)` - The stage's error, or the exceeded ceilings
+pub fn track(
+    stage: &str,
+    run: impl FnOnce() -> Result<(), tester::CaseError>,
+) -> Result<(), tester::CaseError> {
+    STAGE_USAGE.lock().unwrap_or_else(|err| err.into_inner()).clear();
+    let result = run();
+    let usage = std::mem::take(&mut *STAGE_USAGE.lock().unwrap_or_else(|err| err.into_inner()));
+    LAST_STAGE_UNITS.store(usage.iter().map(|entry| entry.units).sum(), Ordering::Relaxed);
+
+    if is_debug_mode() {
+        log_summary(stage, &usage);
+    }
+
+    result?;
+    load_limits()
+        .and_then(|limits| check_limits(&usage, &limits))
+        .map_err(crate::helpers::to_case_error)
+}
+
+/// The total compute units consumed by the last stage [`track`] ran.
+pub fn last_stage_units() -> u64 {
+    LAST_STAGE_UNITS.load(Ordering::Relaxed)
+}
+
+/// Forget the last stage's total, so a case that fails before [`track`]
+/// runs reports no compute units rather than the previous case's.
+pub fn reset_last_stage_units() {
+    LAST_STAGE_UNITS.store(0, Ordering::Relaxed);
+}
+
+/// Log the number of calls and the total and peak compute units of every
+/// instruction a stage executed.
+fn log_summary(stage: &str, usage: &[ComputeUsage]) {
+    let mut summary: Vec<(&str, usize, u64, u64)> = Vec::new();
+    for entry in usage {
+        match summary.iter_mut().find(|(name, ..)| *name == entry.instruction) {
+            Some((_, calls, total, peak)) => {
+                *calls += 1;
+                *total += entry.units;
+                *peak = (*peak).max(entry.units);
+            }
+            None => summary.push((&entry.instruction, 1, entry.units, entry.units)),
+        }
+    }
+
+    report::log(format!("{}: compute units by instruction", stage));
+    for (name, calls, total, peak) in summary {
+        report::log(format!(
+            "  {:<20} calls {:>4}  total {:>10}  peak {:>8}",
+            name, calls, total, peak
+        ));
+    }
+}
+
+/// Check recorded usage against ceilings.
+///
+/// An instruction's ceiling applies to its most expensive call.
+///
+/// # Arguments
+///
+/// * `usage` - The usage recorded during a stage
+/// * `limits` - The ceiling of each instruction that has one
+///
+/// # Returns
+///
+/// * `Ok(())` - If no ceilings are configured or none was exceeded
+/// * `Err(TestContextError)` - Listing every instruction over its ceiling
+fn check_limits(usage: &[ComputeUsage], limits: &[(String, u64)]) -> Result<(), TestContextError> {
+    let mut exceeded = Vec::new();
+    for (name, limit) in limits {
+        let peak = usage.iter().filter(|entry| entry.instruction == *name).map(|e| e.units).max();
+        if let Some(peak) = peak &&
+            peak > *limit
+        {
+            exceeded.push(format!(
+                "{} used {} compute units, above its limit of {}",
+                name, peak, limit
+            ));
+        }
+    }
+
+    if exceeded.is_empty() {
+        Ok(())
+    } else {
+        Err(TestContextError::ValidationError(exceeded.join("; ")))
+    }
+}
+
+/// Parse the ceilings from [`COMPUTE_UNIT_LIMITS_ENV`].
+///
+/// Names must be instructions from [`LENDING_INSTRUCTIONS`], so that a typo
+/// does not silently disable a ceiling.
+fn load_limits() -> Result<Vec<(String, u64)>, TestContextError> {
+    match std::env::var(COMPUTE_UNIT_LIMITS_ENV) {
+        Ok(value) => parse_limits(&value),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Parse ceilings written as comma-separated `name=units` pairs.
+fn parse_limits(value: &str) -> Result<Vec<(String, u64)>, TestContextError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, units) = entry
+                .split_once('=')
+                .and_then(|(name, units)| Some((name.trim(), units.trim().parse().ok()?)))
+                .ok_or_else(|| {
+                    TestContextError::ValidationError(format!(
+                        "invalid entry `{}` in {}, expected name=units",
+                        entry, COMPUTE_UNIT_LIMITS_ENV
+                    ))
+                })?;
+            if !LENDING_INSTRUCTIONS.contains(&name) {
+                return Err(TestContextError::ValidationError(format!(
+                    "unknown instruction `{}` in {}, expected one of: {}",
+                    name,
+                    COMPUTE_UNIT_LIMITS_ENV,
+                    LENDING_INSTRUCTIONS.join(", ")
+                )));
+            }
+            Ok((name.to_string(), units))
+        })
+        .collect()
+}
+
+/// Whether the user's `stackclass.yml` sets `debug: true`.
+fn is_debug_mode() -> bool {
+    crate::helpers::get_repo_dir()
+        .ok()
+        .and_then(|repo_dir| config_value(&repo_dir, "debug"))
+        .is_some_and(|value| value == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(instruction: &str, units: u64) -> ComputeUsage {
+        ComputeUsage { instruction: instruction.to_string(), units }
+    }
+
+    fn error(result: Result<impl std::fmt::Debug, TestContextError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_limits() {
+        assert_eq!(
+            parse_limits(" deposit = 40000, borrow=60000 ,").unwrap(),
+            [("deposit".to_string(), 40_000), ("borrow".to_string(), 60_000)]
+        );
+        assert!(parse_limits("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_limits() {
+        for value in ["deposit", "deposit=", "deposit=many", "deposit=-1"] {
+            assert!(error(parse_limits(value)).contains("expected name=units"), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_instructions() {
+        let message = error(parse_limits("deposit=1000, depositt=1000"));
+        assert!(message.contains("unknown instruction `depositt`"));
+        assert!(message.contains("expected one of: initialize"));
+    }
+
+    #[test]
+    fn applies_each_limit_to_the_peak_call() {
+        let recorded = [usage("deposit", 30_000), usage("deposit", 45_000), usage("repay", 9_000)];
+        let limits = [("deposit".to_string(), 45_000), ("repay".to_string(), 10_000)];
+        assert!(check_limits(&recorded, &limits).is_ok());
+
+        let limits = [("deposit".to_string(), 40_000), ("repay".to_string(), 8_000)];
+        let message = error(check_limits(&recorded, &limits));
+        assert!(message.contains("deposit used 45000 compute units, above its limit of 40000"));
+        assert!(message.contains("repay used 9000 compute units, above its limit of 8000"));
+    }
+
+    #[test]
+    fn ignores_limits_of_instructions_that_did_not_run() {
+        let limits = [("borrow".to_string(), 1)];
+        assert!(check_limits(&[usage("deposit", 50_000)], &limits).is_ok());
+        assert!(check_limits(&[usage("deposit", 50_000)], &[]).is_ok());
+    }
+}
@@ -20,22 +20,249 @@
 
 use std::sync::Arc;
 
-use tester::{Case, Definition};
+use tester::{Case, CaseError, Definition, Harness};
 
-use crate::stages::{
-    base::*,
-    extensions::{
-        account_structure::{as1, as2, as3, as4},
-        interest::{in1, in2, in3, in4},
-        lending_core::{lc1, lc2, lc3, lc4},
-        liquidation::{li1, li2, li3, li4, li5},
-        oracle::{or1, or2, or3, or4},
-        pda::{pa1, pa2, pa3, pa4},
-        security::{se1, se2, se3, se4},
-        treasury::{tr1, tr2, tr3, tr4},
+use crate::{
+    compute,
+    hints::{self, FailureKind},
+    prerequisites, probe, report,
+    stages::{
+        base::*,
+        extensions::{
+            account_structure::{as1, as2, as3, as4},
+            interest::{in1, in2, in3, in4},
+            lending_core::{lc1, lc2, lc3, lc4},
+            liquidation::{li1, li2, li3, li4, li5},
+            oracle::{or1, or2, or3, or4},
+            pda::{pa1, pa2, pa3, pa4},
+            security::{se1, se2, se3, se4},
+            treasury::{tr1, tr2, tr3, tr4},
+        },
     },
 };
 
+/// A stage of the challenge.
+pub struct Stage {
+    /// The stage slug, e.g. `lc3`.
+    pub slug: &'static str,
+    /// The module the stage belongs to, e.g. `lending_core`.
+    pub module: &'static str,
+    /// The stage title, e.g. `LTV Calculation`.
+    pub title: &'static str,
+    /// Stages whose capabilities this stage relies on, e.g. `cp6` for a
+    /// working deposit.
+    pub prerequisites: &'static [&'static str],
+    /// Hints specific to this stage, shown instead of the general hint for
+    /// their kind of failure.
+    pub hints: &'static [(FailureKind, &'static str)],
+    /// Instructions the stage sends, checked against the program's
+    /// capabilities before it runs.
+    pub instructions: &'static [&'static str],
+    run: fn(&Harness) -> Result<(), CaseError>,
+}
+
+impl Stage {
+    /// Declare the stages this stage relies on.
+    const fn requires(self, prerequisites: &'static [&'static str]) -> Self {
+        Stage { prerequisites, ..self }
+    }
+
+    /// Declare hints for kinds of failure specific to this stage.
+    const fn hints(self, hints: &'static [(FailureKind, &'static str)]) -> Self {
+        Stage { hints, ..self }
+    }
+
+    /// Declare the instructions this stage sends.
+    const fn uses(self, instructions: &'static [&'static str]) -> Self {
+        Stage { instructions, ..self }
+    }
+
+    /// Run the stage's test.
+    pub fn run(&self, harness: &Harness) -> Result<(), CaseError> {
+        (self.run)(harness)
+    }
+
+    /// Look up a stage by slug.
+    pub fn find(slug: &str) -> Option<&'static Stage> {
+        STAGES.iter().find(|stage| stage.slug == slug)
+    }
+}
+
+const fn stage(
+    slug: &'static str,
+    module: &'static str,
+    title: &'static str,
+    run: fn(&Harness) -> Result<(), CaseError>,
+) -> Stage {
+    Stage { slug, module, title, prerequisites: &[], hints: &[], instructions: &[], run }
+}
+
+/// Every stage, in course order.
+pub const STAGES: &[Stage] = &[
+    // Base Stages (7 stages)
+    stage("be1", "base", "Environment Setup", be1::test_env_setup),
+    stage("rs2", "base", "Rust Basics", rs2::test_rust_basics).requires(&["be1"]),
+    stage("sm3", "base", "Solana Model", sm3::test_solana_model).requires(&["be1"]),
+    stage("at4", "base", "Anchor Try", at4::test_anchor_try).requires(&["be1"]).hints(&[(
+        FailureKind::ProgramIdMismatch,
+        "Anchor.toml lists a different program ID than `declare_id!`. Run `anchor keys \
+         sync` so both use target/deploy/lending_program-keypair.json, then `anchor \
+         build`.",
+    )]),
+    stage("st5", "base", "SPL Token Basics", st5::test_spl_token_basics).requires(&["at4"]),
+    stage("cp6", "base", "Basic Deposit", cp6::test_basic_deposit)
+        .requires(&["st5"])
+        .uses(&["deposit"])
+        .hints(&[
+            (
+                FailureKind::MissingInstruction,
+                "Add a `deposit(ctx, amount: u64)` instruction to your program.",
+            ),
+            (
+                FailureKind::SeedsMismatch,
+                "`deposit` expects the bank at seeds [mint], the treasury at \
+                 [\"treasury\", mint] and the user at [signer].",
+            ),
+        ]),
+    stage("tt7", "base", "Basic Withdraw", tt7::test_basic_withdraw)
+        .requires(&["cp6"])
+        .uses(&["withdraw"])
+        .hints(&[(
+            FailureKind::MissingInstruction,
+            "Add a `withdraw(ctx, amount: u64)` instruction to your program.",
+        )]),
+    // Extension Modules (8 modules × 4 stages = 32 cases)
+    // PDA Module
+    stage("pa1", "pda", "PDA Concept", pa1::test_pda_concept).requires(&["at4"]),
+    stage("pa2", "pda", "PDA Derivation", pa2::test_pda_derivation).requires(&["pa1"]).hints(&[(
+        FailureKind::SeedsMismatch,
+        "Derive each PDA with `seeds = [...]` and `bump` in its #[account] constraint, \
+         using the same seeds as the stage: [mint] for banks and [owner] for users.",
+    )]),
+    stage("pa3", "pda", "Bump Seeds", pa3::test_pda_bump_seeds).requires(&["pa2"]),
+    stage("pa4", "pda", "PDA Practice", pa4::test_pda_practice).requires(&["pa3"]),
+    // Treasury Module
+    stage("tr1", "treasury", "Treasury Intro", tr1::test_treasury_intro).requires(&["cp6"]),
+    stage("tr2", "treasury", "Treasury Creation", tr2::test_treasury_creation)
+        .requires(&["tr1"])
+        .hints(&[(
+            FailureKind::SeedsMismatch,
+            "The treasury is a token account at seeds [b\"treasury\", mint.key().as_ref()], \
+             owned by itself.",
+        )]),
+    stage("tr3", "treasury", "Treasury Security", tr3::test_treasury_security).requires(&["tr2"]),
+    stage("tr4", "treasury", "Treasury Practice", tr4::test_treasury_practice).requires(&["tr3"]),
+    // Account Structure Module
+    stage("as1", "account_structure", "Bank Account", as1::test_bank_account).requires(&["cp6"]),
+    stage("as2", "account_structure", "User Account", as2::test_user_account).requires(&["as1"]),
+    stage("as3", "account_structure", "Account Space", as3::test_account_space)
+        .requires(&["as2"])
+        .hints(&[(
+            FailureKind::WrongAccountSize,
+            "Allocate `8 + Bank::INIT_SPACE` and `8 + User::INIT_SPACE` bytes with \
+             #[derive(InitSpace)], keeping the fields in the challenge's order.",
+        )]),
+    stage("as4", "account_structure", "Account Practice", as4::test_account_practice)
+        .requires(&["as3"]),
+    // Lending Core Module
+    stage("lc1", "lending_core", "Borrow Basics", lc1::test_borrow_basics)
+        .requires(&["cp6", "tt7"])
+        .uses(&["borrow"])
+        .hints(&[(
+            FailureKind::MissingInstruction,
+            "Add a `borrow(ctx, amount: u64)` instruction to your program.",
+        )]),
+    stage("lc2", "lending_core", "Repay Basics", lc2::test_repay_basics)
+        .requires(&["lc1"])
+        .uses(&["borrow", "repay"])
+        .hints(&[(
+            FailureKind::MissingInstruction,
+            "Add a `repay(ctx, amount: u64)` instruction to your program.",
+        )]),
+    stage("lc3", "lending_core", "LTV Calculation", lc3::test_ltv_calculation)
+        .requires(&["lc1"])
+        .uses(&["borrow"]),
+    stage("lc4", "lending_core", "Core Practice", lc4::test_core_practice)
+        .requires(&["lc2", "lc3"])
+        .uses(&["borrow", "repay"]),
+    // Oracle Module
+    stage("or1", "oracle", "Oracle Concept", or1::test_oracle_concept).requires(&["at4"]),
+    stage("or2", "oracle", "Pyth Integration", or2::test_pyth_integration)
+        .requires(&["or1"])
+        .hints(&[(
+            FailureKind::AccountNotInitialized,
+            "Pass the Pyth `PriceUpdateV2` account as `price_update: Account<'info, \
+             PriceUpdateV2>` and read it with `get_price_no_older_than`.",
+        )]),
+    stage("or3", "oracle", "Price Fetching", or3::test_price_fetching).requires(&["or2"]),
+    stage("or4", "oracle", "Oracle Practice", or4::test_oracle_practice).requires(&["or3"]),
+    // Liquidation Module
+    stage("li1", "liquidation", "Health Factor", li1::test_health_factor).requires(&["lc1", "or2"]),
+    stage("li2", "liquidation", "Liquidation Trigger", li2::test_liquidation_trigger)
+        .requires(&["li1"])
+        .uses(&["liquidate"]),
+    stage("li3", "liquidation", "Liquidation Process", li3::test_liquidation_process)
+        .requires(&["li2"])
+        .uses(&["liquidate"])
+        .hints(&[(
+            FailureKind::MissingInstruction,
+            "Add a `liquidate(ctx)` instruction to your program.",
+        )]),
+    stage("li4", "liquidation", "Liquidation Bonus", li4::test_liquidation_bonus)
+        .requires(&["li3"])
+        .uses(&["liquidate"]),
+    stage("li5", "liquidation", "Liquidation Practice", li5::test_liquidation_practice)
+        .requires(&["li4"])
+        .uses(&["liquidate"]),
+    // Interest Module
+    stage("in1", "interest", "Interest Basics", in1::test_interest_basics).requires(&["lc1"]),
+    stage("in2", "interest", "Accrued Interest", in2::test_accrued_interest).requires(&["in1"]),
+    stage("in3", "interest", "Rate Models", in3::test_rate_models)
+        .requires(&["in2"])
+        .uses(&["accrue_interest"]),
+    stage("in4", "interest", "Interest Practice", in4::test_interest_practice).requires(&["in3"]),
+    // Security Module
+    stage("se1", "security", "Common Vulnerabilities", se1::test_common_vulnerabilities)
+        .requires(&["cp6", "tt7"]),
+    stage("se2", "security", "Reentrancy Protection", se2::test_reentrancy_protection)
+        .requires(&["se1"]),
+    stage("se3", "security", "Account Validation", se3::test_account_validation)
+        .requires(&["se1"])
+        .hints(&[(
+            FailureKind::SeedsMismatch,
+            "Every PDA your program accepts should be checked with `seeds` and `bump`, so \
+             forged accounts are rejected.",
+        )]),
+    stage("se4", "security", "Security Practice", se4::test_security_practice)
+        .requires(&["se2", "se3"]),
+];
+
+/// Create a test case that fails early if the program lacks an instruction
+/// the stage uses, whose instructions are checked against the compute-unit
+/// ceilings, and whose result is recorded in the report. A failure gets a
+/// hint for its kind, and one caused by a broken prerequisite names that
+/// prerequisite.
+///
+/// # Arguments
+///
+/// * `stage` - The stage to run
+///
+/// # Returns
+///
+/// * `Case` - The test case
+fn case(stage: &'static Stage) -> Case {
+    Case::new(
+        stage.slug,
+        Arc::new(move |harness: &Harness| {
+            report::record(stage.slug, || {
+                let result = probe::require(stage.instructions)
+                    .and_then(|()| compute::track(stage.slug, || stage.run(harness)));
+                prerequisites::explain(stage, harness, hints::annotate(stage, result))
+            })
+        }),
+    )
+}
+
 /// Build the test definition for the lending program.
 ///
 /// This function creates a Definition struct that includes all test cases
@@ -49,58 +276,7 @@ pub fn build() -> Definition {
     Definition {
         executable_name: "your_program.sh".to_string(),
         legacy_executable_name: None,
-        cases: vec![
-            // Base Stages (7 stages)
-            Case::new("be1", Arc::new(be1::test_env_setup)),
-            Case::new("rs2", Arc::new(rs2::test_rust_basics)),
-            Case::new("sm3", Arc::new(sm3::test_solana_model)),
-            Case::new("at4", Arc::new(at4::test_anchor_try)),
-            Case::new("st5", Arc::new(st5::test_spl_token_basics)),
-            Case::new("cp6", Arc::new(cp6::test_basic_deposit)),
-            Case::new("tt7", Arc::new(tt7::test_basic_withdraw)),
-            // Extension Modules (8 modules × 4 stages = 32 cases)
-            // PDA Module
-            Case::new("pa1", Arc::new(pa1::test_pda_concept)),
-            Case::new("pa2", Arc::new(pa2::test_pda_derivation)),
-            Case::new("pa3", Arc::new(pa3::test_pda_bump_seeds)),
-            Case::new("pa4", Arc::new(pa4::test_pda_practice)),
-            // Treasury Module
-            Case::new("tr1", Arc::new(tr1::test_treasury_intro)),
-            Case::new("tr2", Arc::new(tr2::test_treasury_creation)),
-            Case::new("tr3", Arc::new(tr3::test_treasury_security)),
-            Case::new("tr4", Arc::new(tr4::test_treasury_practice)),
-            // Account Structure Module
-            Case::new("as1", Arc::new(as1::test_bank_account)),
-            Case::new("as2", Arc::new(as2::test_user_account)),
-            Case::new("as3", Arc::new(as3::test_account_space)),
-            Case::new("as4", Arc::new(as4::test_account_practice)),
-            // Lending Core Module
-            Case::new("lc1", Arc::new(lc1::test_borrow_basics)),
-            Case::new("lc2", Arc::new(lc2::test_repay_basics)),
-            Case::new("lc3", Arc::new(lc3::test_ltv_calculation)),
-            Case::new("lc4", Arc::new(lc4::test_core_practice)),
-            // Oracle Module
-            Case::new("or1", Arc::new(or1::test_oracle_concept)),
-            Case::new("or2", Arc::new(or2::test_pyth_integration)),
-            Case::new("or3", Arc::new(or3::test_price_fetching)),
-            Case::new("or4", Arc::new(or4::test_oracle_practice)),
-            // Liquidation Module
-            Case::new("li1", Arc::new(li1::test_health_factor)),
-            Case::new("li2", Arc::new(li2::test_liquidation_trigger)),
-            Case::new("li3", Arc::new(li3::test_liquidation_process)),
-            Case::new("li4", Arc::new(li4::test_liquidation_bonus)),
-            Case::new("li5", Arc::new(li5::test_liquidation_practice)),
-            // Interest Module
-            Case::new("in1", Arc::new(in1::test_interest_basics)),
-            Case::new("in2", Arc::new(in2::test_accrued_interest)),
-            Case::new("in3", Arc::new(in3::test_rate_models)),
-            Case::new("in4", Arc::new(in4::test_interest_practice)),
-            // Security Module
-            Case::new("se1", Arc::new(se1::test_common_vulnerabilities)),
-            Case::new("se2", Arc::new(se2::test_reentrancy_protection)),
-            Case::new("se3", Arc::new(se3::test_account_validation)),
-            Case::new("se4", Arc::new(se4::test_security_practice)),
-        ],
+        cases: STAGES.iter().map(case).collect(),
         ..Default::default()
     }
 }
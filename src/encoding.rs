@@ -0,0 +1,309 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How instruction data identifies the instruction to run.
+//!
+//! Anchor programs prefix the Borsh-encoded arguments with the 8-byte
+//! `global:<name>` sighash. Native programs (plain `solana-program` or
+//! Pinocchio) usually prefix them with a one-byte tag instead: the variant
+//! index of a Borsh instruction enum, or a hand-assigned byte.
+//!
+//! The encoding is selected in the user's `stackclass.yml`:
+//!
+//! ```yaml
+//! instruction_encoding: native
+//! instruction_tags: initialize=0, deposit=1, withdraw=2
+//! ```
+//!
+//! `instruction_encoding` is `anchor` (the default) or `native`. For native
+//! programs, `instruction_tags` assigns each instruction its tag; without it
+//! the tags follow the order of [`LENDING_INSTRUCTIONS`].
+//!
+//! The encoding also covers what the program writes and returns beyond the
+//! tag: the header in front of its `Bank` and `User` accounts, and the errors
+//! that stand in for Anchor's framework errors. Native programs store the
+//! account fields with no header and report failures with their own codes,
+//! so a stage expecting a named Anchor error accepts any error from them.
+
+use crate::{
+    assertions::AnchorError,
+    helpers::{LENDING_INSTRUCTIONS, anchor_discriminator, config_value, get_repo_dir},
+    mollusk::ProgramLoadError,
+};
+use solana_instruction_error::InstructionError;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Encodes which instruction a program should run.
+pub trait InstructionEncoding: Send + Sync {
+    /// The bytes that precede an instruction's arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The instruction's snake_case name, e.g. `deposit`
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Vec<u8>)` - The instruction's tag
+    /// * `None` - If the encoding has no tag for the instruction
+    fn tag(&self, name: &str) -> Option<Vec<u8>>;
+
+    /// The error a program returns for a tag it does not recognize, if it is
+    /// distinct from the error for malformed arguments.
+    fn unknown_instruction_error(&self) -> Option<InstructionError>;
+
+    /// The errors a handler returns when it runs without arguments or
+    /// accounts, which show that the program implements the instruction.
+    fn handler_errors(&self) -> Vec<InstructionError>;
+
+    /// The bytes that precede an account's fields.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The account type's name, e.g. `Bank`
+    fn account_header(&self, name: &str) -> Vec<u8>;
+
+    /// Whether an instruction's error is the one a stage expects.
+    ///
+    /// # Arguments
+    ///
+    /// * `expected` - The expected Anchor or program error
+    /// * `actual` - The error the instruction returned
+    fn matches_error(&self, expected: AnchorError, actual: &InstructionError) -> bool;
+}
+
+/// Anchor's `global:<name>` sighash discriminators.
+#[derive(Debug, Clone, Copy)]
+pub struct AnchorEncoding;
+
+impl InstructionEncoding for AnchorEncoding {
+    fn tag(&self, name: &str) -> Option<Vec<u8>> {
+        Some(anchor_discriminator(&format!("global:{}", name)).to_vec())
+    }
+
+    fn unknown_instruction_error(&self) -> Option<InstructionError> {
+        Some(InstructionError::Custom(AnchorError::InstructionFallbackNotFound.code()))
+    }
+
+    fn handler_errors(&self) -> Vec<InstructionError> {
+        [AnchorError::InstructionDidNotDeserialize, AnchorError::AccountNotEnoughKeys]
+            .into_iter()
+            .map(|error| InstructionError::Custom(error.code()))
+            .collect()
+    }
+
+    fn account_header(&self, name: &str) -> Vec<u8> {
+        anchor_discriminator(&format!("account:{}", name)).to_vec()
+    }
+
+    fn matches_error(&self, expected: AnchorError, actual: &InstructionError) -> bool {
+        *actual == InstructionError::Custom(expected.code())
+    }
+}
+
+/// A one-byte tag per instruction, as written by a Borsh enum or by hand.
+#[derive(Debug, Clone)]
+pub struct NativeEncoding {
+    tags: Vec<(String, u8)>,
+}
+
+impl NativeEncoding {
+    /// Tags in the order of [`LENDING_INSTRUCTIONS`], as a Borsh enum with
+    /// one variant per instruction would assign them.
+    pub fn in_course_order() -> Self {
+        let tags = LENDING_INSTRUCTIONS
+            .iter()
+            .zip(0u8..)
+            .map(|(name, tag)| (name.to_string(), tag))
+            .collect();
+        NativeEncoding { tags }
+    }
+
+    /// Parse tags written as comma-separated `name=tag` pairs.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The pairs, e.g. `initialize=0, deposit=1`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(NativeEncoding)` - The encoding
+    /// * `Err(ProgramLoadError)` - If a pair is malformed or a tag is used twice
+    pub fn parse(value: &str) -> Result<Self, ProgramLoadError> {
+        let mut tags: Vec<(String, u8)> = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (name, tag) = entry
+                .split_once('=')
+                .and_then(|(name, tag)| Some((name.trim(), tag.trim().parse::<u8>().ok()?)))
+                .ok_or_else(|| {
+                    ProgramLoadError::InvalidConfig(format!(
+                        "invalid entry `{}` in instruction_tags, expected name=tag with a tag \
+                         from 0 to 255",
+                        entry
+                    ))
+                })?;
+            if let Some((other, _)) = tags.iter().find(|(_, used)| *used == tag) {
+                return Err(ProgramLoadError::InvalidConfig(format!(
+                    "`{}` and `{}` both have tag {} in instruction_tags",
+                    other, name, tag
+                )));
+            }
+            tags.push((name.to_string(), tag));
+        }
+        Ok(NativeEncoding { tags })
+    }
+}
+
+impl InstructionEncoding for NativeEncoding {
+    fn tag(&self, name: &str) -> Option<Vec<u8>> {
+        self.tags.iter().find(|(tagged, _)| tagged == name).map(|(_, tag)| vec![*tag])
+    }
+
+    fn unknown_instruction_error(&self) -> Option<InstructionError> {
+        None
+    }
+
+    fn handler_errors(&self) -> Vec<InstructionError> {
+        vec![InstructionError::NotEnoughAccountKeys]
+    }
+
+    fn account_header(&self, _name: &str) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn matches_error(&self, expected: AnchorError, actual: &InstructionError) -> bool {
+        match expected {
+            AnchorError::Custom(code) => *actual == InstructionError::Custom(code),
+            _ => true,
+        }
+    }
+}
+
+/// The encoding selected for a repository directory, if any.
+static ENCODING: Mutex<Option<(PathBuf, Arc<dyn InstructionEncoding>)>> = Mutex::new(None);
+
+/// Select the encoding from the user's `stackclass.yml`, once per process.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(Arc<dyn InstructionEncoding>)` - The selected encoding
+/// * `Err(ProgramLoadError)` - If the configuration is invalid
+pub fn load(repo_dir: &Path) -> Result<Arc<dyn InstructionEncoding>, ProgramLoadError> {
+    let mut cached = ENCODING.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some((dir, encoding)) = cached.as_ref() &&
+        dir == repo_dir
+    {
+        return Ok(encoding.clone());
+    }
+
+    let encoding: Arc<dyn InstructionEncoding> =
+        match config_value(repo_dir, "instruction_encoding").as_deref() {
+            None | Some("anchor") => Arc::new(AnchorEncoding),
+            Some("native") => match config_value(repo_dir, "instruction_tags") {
+                Some(tags) => Arc::new(NativeEncoding::parse(&tags)?),
+                None => Arc::new(NativeEncoding::in_course_order()),
+            },
+            Some(other) => {
+                return Err(ProgramLoadError::InvalidConfig(format!(
+                    "unknown instruction_encoding `{}`, expected `anchor` or `native`",
+                    other
+                )));
+            }
+        };
+    *cached = Some((repo_dir.to_path_buf(), encoding.clone()));
+    Ok(encoding)
+}
+
+/// The encoding for the repository under test.
+///
+/// Anchor's is used when no repository is set.
+///
+/// # Returns
+///
+/// * `Ok(Arc<dyn InstructionEncoding>)` - The selected encoding
+/// * `Err(ProgramLoadError)` - If the repository's configuration is invalid
+pub fn current() -> Result<Arc<dyn InstructionEncoding>, ProgramLoadError> {
+    match get_repo_dir() {
+        Ok(repo_dir) => load(&repo_dir),
+        Err(_) => Ok(Arc::new(AnchorEncoding)),
+    }
+}
+
+/// Instruction data for the repository under test.
+///
+/// # Arguments
+///
+/// * `name` - The instruction's snake_case name, e.g. `withdraw`
+/// * `args` - The Borsh-encoded arguments
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The instruction's tag followed by `args`
+/// * `Err(ProgramLoadError)` - If the encoding has no tag for the instruction
+pub fn instruction_data(name: &str, args: &[u8]) -> Result<Vec<u8>, ProgramLoadError> {
+    let mut data = current()?.tag(name).ok_or_else(|| {
+        ProgramLoadError::InvalidConfig(format!("no tag for `{}` in instruction_tags", name))
+    })?;
+    data.extend_from_slice(args);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_native_tags() {
+        let encoding = NativeEncoding::parse("initialize=0, deposit = 3,withdraw=255,").unwrap();
+        assert_eq!(encoding.tag("initialize"), Some(vec![0]));
+        assert_eq!(encoding.tag("deposit"), Some(vec![3]));
+        assert_eq!(encoding.tag("withdraw"), Some(vec![255]));
+        assert_eq!(encoding.tag("borrow"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_native_tags() {
+        for value in ["deposit", "deposit=", "deposit=256", "deposit=-1", "deposit=one"] {
+            assert!(
+                matches!(NativeEncoding::parse(value), Err(ProgramLoadError::InvalidConfig(_))),
+                "{} should be rejected",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_native_tags() {
+        let Err(ProgramLoadError::InvalidConfig(message)) =
+            NativeEncoding::parse("deposit=1, withdraw=1")
+        else {
+            panic!("a tag used twice should be rejected");
+        };
+        assert!(message.contains("`deposit` and `withdraw` both have tag 1"));
+    }
+
+    #[test]
+    fn course_order_follows_the_instruction_list() {
+        let encoding = NativeEncoding::in_course_order();
+        for (name, tag) in LENDING_INSTRUCTIONS.iter().zip(0u8..) {
+            assert_eq!(encoding.tag(name), Some(vec![tag]));
+        }
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Randomized protocol fuzzing against the user's program.
+//!
+//! Each sequence sets up a two-asset market (SOL and USDC banks, three
+//! funded users and Pyth prices) and runs random deposits, withdrawals,
+//! borrows, repayments, liquidations, price changes and clock warps as a
+//! [`Scenario`]. Every step may succeed or fail, but the program must not
+//! abort, every successful deposit, withdrawal, borrow, repayment and
+//! liquidation must agree with the reference model, and the protocol
+//! invariants must hold after every instruction. Clock warps are not
+//! modelled on their own; interest is only compared where a scenario runs
+//! `accrue_interest`.
+//!
+//! A failing sequence is shrunk to a minimal reproduction by dropping steps
+//! and halving amounts, then printed in the scenario format together with
+//! the seed that replays it.
+
+use crate::{
+    helpers::get_repo_dir,
+    mollusk::TestContextError,
+    scenario::{
+        BankSpec, ErrorSpec, ExpectSpec, InstructionStep, MintSpec, PriceSpec, SOL_USD_FEED_ID,
+        Scenario, ScenarioRun, Step, StepOutcome, USDC_USD_FEED_ID, UserSpec, WalletSpec,
+    },
+};
+use solana_instruction_error::InstructionError;
+use std::{collections::BTreeMap, path::Path, process::ExitCode};
+
+/// Environment variable that enables fuzzing, holding the number of
+/// sequences to run.
+pub const FUZZ_ENV: &str = "STACKCLASS_FUZZ";
+
+/// Environment variable holding the seed of the first sequence, to replay a
+/// reported failure.
+pub const FUZZ_SEED_ENV: &str = "STACKCLASS_FUZZ_SEED";
+
+/// Steps generated per sequence.
+const STEPS_PER_SEQUENCE: usize = 24;
+
+/// Upper bound on the sequences run while shrinking a failure.
+const MAX_SHRINK_RUNS: usize = 200;
+
+/// The users of the generated market.
+const WALLETS: &[&str] = &["alice", "bob", "carol"];
+
+/// The starting unix timestamp of every sequence.
+const START_TIME: i64 = 1_700_000_000;
+
+/// Exponent of the generated prices.
+const PRICE_EXPONENT: i32 = -8;
+
+/// One of the market's assets, with the names the scenario uses for it.
+#[derive(Debug, Clone, Copy)]
+struct MarketAsset {
+    mint: &'static str,
+    price: &'static str,
+    decimals: u8,
+    /// The largest amount generated for an action, in base units.
+    max_amount: u64,
+}
+
+const SOL: MarketAsset =
+    MarketAsset { mint: "sol", price: "sol_usd", decimals: 9, max_amount: 40_000_000_000 };
+const USDC: MarketAsset =
+    MarketAsset { mint: "usdc", price: "usdc_usd", decimals: 6, max_amount: 8_000_000_000 };
+
+/// A property violation at a step of a sequence.
+#[derive(Debug)]
+struct Failure {
+    step: usize,
+    message: String,
+}
+
+/// A small, seedable pseudo-random generator (SplitMix64), so sequences
+/// replay identically from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound.max(1)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// Run the fuzzer and report the first failing sequence.
+///
+/// # Arguments
+///
+/// * `iterations` - The value of [`FUZZ_ENV`]: the number of sequences
+///
+/// # Returns
+///
+/// * `ExitCode` - Success if every sequence passed
+pub fn run(iterations: &str) -> ExitCode {
+    let Ok(iterations) = iterations.trim().parse::<u64>() else {
+        eprintln!("{} must be a number of sequences, got `{}`", FUZZ_ENV, iterations);
+        return ExitCode::FAILURE;
+    };
+    let seed = match std::env::var(FUZZ_SEED_ENV) {
+        Ok(seed) => match seed.trim().parse::<u64>() {
+            Ok(seed) => seed,
+            Err(_) => {
+                eprintln!("{} must be an unsigned integer, got `{}`", FUZZ_SEED_ENV, seed);
+                return ExitCode::FAILURE;
+            }
+        },
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64),
+    };
+    let repo_dir = match get_repo_dir() {
+        Ok(repo_dir) => repo_dir,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("fuzzing {} sequences of {} steps from seed {}", iterations, STEPS_PER_SEQUENCE, seed);
+    for iteration in 0..iterations {
+        let sequence_seed = seed.wrapping_add(iteration);
+        let scenario = generate(sequence_seed);
+        let failure = match check(&repo_dir, &scenario) {
+            Ok((_, None)) => continue,
+            Ok((_, Some(failure))) => failure,
+            Err(err) => {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        println!(
+            "sequence {} (seed {}) failed at step {} (`{}`): {}",
+            iteration + 1,
+            sequence_seed,
+            failure.step + 1,
+            scenario.steps[failure.step].label(),
+            failure.message
+        );
+        let (minimal, failure) = shrink(&repo_dir, scenario, failure);
+        let reproduction = annotate(&repo_dir, minimal, &failure);
+        println!(
+            "minimal reproduction ({} steps), failing at step {}: {}",
+            reproduction.steps.len(),
+            failure.step + 1,
+            failure.message
+        );
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&reproduction).unwrap_or_else(|err| err.to_string())
+        );
+        println!("replay with {}=1 {}={}", FUZZ_ENV, FUZZ_SEED_ENV, sequence_seed);
+        return ExitCode::FAILURE;
+    }
+
+    println!("all {} sequences passed", iterations);
+    ExitCode::SUCCESS
+}
+
+/// Generate a random sequence over a fresh market.
+fn generate(seed: u64) -> Scenario {
+    let mut rng = Rng(seed);
+    let whole = |asset: MarketAsset| 10u64.pow(u32::from(asset.decimals));
+    let price_unit = 10i64.pow(PRICE_EXPONENT.unsigned_abs());
+
+    let wallets = WALLETS
+        .iter()
+        .map(|name| WalletSpec {
+            name: name.to_string(),
+            lamports: 10_000_000_000,
+            tokens: BTreeMap::from([
+                (SOL.mint.to_string(), 100 * whole(SOL)),
+                (USDC.mint.to_string(), 20_000 * whole(USDC)),
+            ]),
+        })
+        .collect();
+    let banks = [SOL, USDC]
+        .iter()
+        .map(|asset| BankSpec {
+            mint: asset.mint.to_string(),
+            authority: None,
+            treasury: 0,
+            fields: BTreeMap::from([
+                ("liquidation_threshold".to_string(), 8000),
+                ("liquidation_bonus".to_string(), 500),
+                ("liquidation_close_factor".to_string(), 5000),
+                ("max_ltv".to_string(), 7500),
+                ("last_updated".to_string(), START_TIME as u64),
+            ]),
+        })
+        .collect();
+    let users = WALLETS
+        .iter()
+        .map(|name| UserSpec {
+            owner: name.to_string(),
+            mint: USDC.mint.to_string(),
+            fields: BTreeMap::from([("last_updated".to_string(), START_TIME as u64)]),
+        })
+        .collect();
+    let mut sol_price = 150 * price_unit;
+    let mut usdc_price = price_unit;
+    let prices = vec![
+        PriceSpec {
+            name: SOL.price.to_string(),
+            feed_id: SOL_USD_FEED_ID.to_string(),
+            price: sol_price,
+            conf: 0,
+            exponent: PRICE_EXPONENT,
+            age: 0,
+        },
+        PriceSpec {
+            name: USDC.price.to_string(),
+            feed_id: USDC_USD_FEED_ID.to_string(),
+            price: usdc_price,
+            conf: 0,
+            exponent: PRICE_EXPONENT,
+            age: 0,
+        },
+    ];
+
+    let steps = (0..STEPS_PER_SEQUENCE)
+        .map(|_| {
+            let user = rng.pick(WALLETS).to_string();
+            let asset = *rng.pick(&[SOL, USDC]);
+            let other = if asset.mint == SOL.mint { USDC } else { SOL };
+            let amount = random_amount(&mut rng, asset);
+            match rng.below(100) {
+                0..30 => instruction("deposit", user, asset, Some(amount), BTreeMap::new()),
+                30..45 => instruction("withdraw", user, asset, Some(amount), BTreeMap::new()),
+                45..65 => instruction(
+                    "borrow",
+                    user,
+                    asset,
+                    Some(amount),
+                    BTreeMap::from([("price_update".to_string(), other.price.to_string())]),
+                ),
+                65..80 => instruction("repay", user, asset, Some(amount), BTreeMap::new()),
+                80..85 => {
+                    let target = rng.pick(WALLETS);
+                    instruction(
+                        "liquidate",
+                        user,
+                        asset,
+                        None,
+                        BTreeMap::from([
+                            ("borrowed_mint".to_string(), asset.mint.to_string()),
+                            ("collateral_mint".to_string(), other.mint.to_string()),
+                            ("price_update".to_string(), other.price.to_string()),
+                            ("user_account".to_string(), format!("user:{}", target)),
+                        ]),
+                    )
+                }
+                85..95 => {
+                    if rng.below(5) == 0 {
+                        // USDC moves a little around its peg.
+                        usdc_price = price_unit * (90 + rng.below(21) as i64) / 100;
+                        Step::SetPrice { set_price: USDC.price.to_string(), price: usdc_price }
+                    } else {
+                        // SOL moves between half and one and a half times
+                        // its last price.
+                        sol_price = (sol_price * (50 + rng.below(101) as i64) / 100).max(1);
+                        Step::SetPrice { set_price: SOL.price.to_string(), price: sol_price }
+                    }
+                }
+                _ => Step::Warp { warp: 60 + rng.below(30 * 24 * 60 * 60) as i64 },
+            }
+        })
+        .collect();
+
+    Scenario {
+        name: format!("fuzz sequence {}", seed),
+        clock: Some(START_TIME),
+        mints: [SOL, USDC]
+            .iter()
+            .map(|asset| MintSpec { name: asset.mint.to_string(), decimals: asset.decimals })
+            .collect(),
+        wallets,
+        banks,
+        users,
+        prices,
+        steps,
+    }
+}
+
+/// A random action amount: usually up to the asset's `max_amount`, and
+/// occasionally zero, one or `u64::MAX`.
+fn random_amount(rng: &mut Rng, asset: MarketAsset) -> u64 {
+    if rng.below(20) == 0 { *rng.pick(&[0, 1, u64::MAX]) } else { 1 + rng.below(asset.max_amount) }
+}
+
+/// An instruction step that may succeed or fail.
+fn instruction(
+    name: &str,
+    signer: String,
+    asset: MarketAsset,
+    amount: Option<u64>,
+    accounts: BTreeMap<String, String>,
+) -> Step {
+    Step::Instruction(InstructionStep {
+        instruction: name.to_string(),
+        signer,
+        mint: Some(asset.mint.to_string()),
+        args: amount.map(|amount| ("amount".to_string(), amount)).into_iter().collect(),
+        accounts,
+        expect: ExpectSpec { any_outcome: true, ..Default::default() },
+    })
+}
+
+/// Run a sequence, stopping at the first property violation.
+///
+/// # Returns
+///
+/// * `Ok((outcomes, failure))` - The outcome of every step that ran, and the violation if any
+/// * `Err(TestContextError)` - If the market could not be set up
+fn check(
+    repo_dir: &Path,
+    scenario: &Scenario,
+) -> Result<(Vec<StepOutcome>, Option<Failure>), TestContextError> {
+    let mut run = ScenarioRun::setup(repo_dir, scenario)?;
+    let mut outcomes = Vec::new();
+    for (step, spec) in scenario.steps.iter().enumerate() {
+        let failure = |message: String| Some(Failure { step, message });
+        match run.run_step(spec) {
+            Err(message) => return Ok((outcomes, failure(message))),
+            Ok(StepOutcome::Executed(Err(InstructionError::ProgramFailedToComplete))) => {
+                return Ok((
+                    outcomes,
+                    failure("the program aborted instead of returning an error".to_string()),
+                ));
+            }
+            Ok(outcome) => outcomes.push(outcome),
+        }
+    }
+    Ok((outcomes, None))
+}
+
+/// Shrink a failing sequence by dropping steps and halving amounts while it
+/// still fails.
+fn shrink(repo_dir: &Path, mut scenario: Scenario, mut failure: Failure) -> (Scenario, Failure) {
+    let mut runs = 0;
+    let still_fails = |candidate: &Scenario, runs: &mut usize| {
+        *runs += 1;
+        check(repo_dir, candidate).ok().and_then(|(_, failure)| failure)
+    };
+    scenario.steps.truncate(failure.step + 1);
+
+    loop {
+        let mut progressed = false;
+
+        let mut index = 0;
+        while index < scenario.steps.len() && runs < MAX_SHRINK_RUNS {
+            let mut candidate = scenario.clone();
+            candidate.steps.remove(index);
+            match still_fails(&candidate, &mut runs) {
+                Some(found) => {
+                    candidate.steps.truncate(found.step + 1);
+                    (scenario, failure) = (candidate, found);
+                    progressed = true;
+                }
+                None => index += 1,
+            }
+        }
+
+        for index in 0..scenario.steps.len() {
+            if runs >= MAX_SHRINK_RUNS {
+                break;
+            }
+            let mut candidate = scenario.clone();
+            let Step::Instruction(step) = &mut candidate.steps[index] else {
+                continue;
+            };
+            let Some(amount) = step.args.get_mut("amount").filter(|amount| **amount > 1) else {
+                continue;
+            };
+            *amount /= 2;
+            if let Some(found) = still_fails(&candidate, &mut runs) {
+                candidate.steps.truncate(found.step + 1);
+                (scenario, failure) = (candidate, found);
+                progressed = true;
+            }
+        }
+
+        if !progressed || runs >= MAX_SHRINK_RUNS {
+            return (scenario, failure);
+        }
+    }
+}
+
+/// Record each step's actual outcome as its expectation, so the reproduction
+/// reads as a regular scenario. The failing step keeps `any_outcome`.
+fn annotate(repo_dir: &Path, mut scenario: Scenario, failure: &Failure) -> Scenario {
+    let outcomes = check(repo_dir, &scenario).map(|(outcomes, _)| outcomes).unwrap_or_default();
+    for (step, outcome) in scenario.steps.iter_mut().zip(outcomes).take(failure.step) {
+        let (Step::Instruction(step), StepOutcome::Executed(result)) = (step, outcome) else {
+            continue;
+        };
+        step.expect = ExpectSpec {
+            error: match result {
+                Ok(()) => None,
+                Err(InstructionError::Custom(code)) => Some(ErrorSpec::Code(code)),
+                Err(_) => Some(ErrorSpec::Name("any".to_string())),
+            },
+            ..Default::default()
+        };
+    }
+    scenario.name = format!("fuzz reproduction: {}", failure.message.lines().next().unwrap_or(""));
+    scenario
+}
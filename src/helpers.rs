@@ -15,30 +15,127 @@
 //! Helper functions for testing the lending program.
 
 #[allow(dead_code)]
-use crate::mollusk::{
-    ProgramLoadError, TestContextError, init_test_context, load_lending_program,
-    load_lending_program_id,
+use crate::{
+    assertions::AnchorError,
+    layout::{Bank, User, bank_address, user_address},
+    mollusk::{
+        ProgramIdSource, ProgramLoadError, TestContextError, find_deploy_keypair,
+        find_lending_program_id, init_test_context, load_lending_program_id, preflight_program,
+    },
+    scenario,
 };
 use mollusk_svm::{program::keyed_account_for_system_program, result::Check};
 use mollusk_svm_programs_token::{associated_token, token};
 use sha2::{Digest, Sha256};
 use solana_account::Account;
 use solana_instruction::{AccountMeta, Instruction};
+use solana_instruction_error::InstructionError;
 use solana_pubkey::Pubkey;
-use std::{path::Path, str::FromStr};
+use std::{path::Path, str::FromStr, sync::OnceLock};
 
 const DEFAULT_LENDING_PROGRAM_ID: &str = "LendZ1111111111111111111111111111111111111";
 
+/// Instructions of the lending program, by their Anchor name.
+pub const LENDING_INSTRUCTIONS: &[&str] = &[
+    "initialize",
+    "init_bank",
+    "init_user",
+    "deposit",
+    "withdraw",
+    "borrow",
+    "repay",
+    "liquidate",
+    "accrue_interest",
+];
+
+/// Repository directory given on the command line, which takes precedence
+/// over `STACKCLASS_REPOSITORY_DIR`.
+static REPO_DIR_OVERRIDE: OnceLock<std::path::PathBuf> = OnceLock::new();
+
+/// Use `repo_dir` as the repository directory for the rest of the process.
+///
+/// # Arguments
+///
+/// * `repo_dir` - The repository directory
+pub fn set_repo_dir(repo_dir: std::path::PathBuf) {
+    let _ = REPO_DIR_OVERRIDE.set(repo_dir);
+}
+
+/// Read a top-level `key: value` entry from the user's `stackclass.yml`.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+/// * `key` - The entry's key, e.g. `debug`
+///
+/// # Returns
+///
+/// * `Some(String)` - The entry's value, without comments or quotes
+/// * `None` - If the file or the entry does not exist
+pub fn config_value(repo_dir: &Path, key: &str) -> Option<String> {
+    let config = std::fs::read_to_string(repo_dir.join("stackclass.yml")).ok()?;
+    parse_config_value(&config, key)
+}
+
+/// Find a top-level scalar entry in YAML text.
+///
+/// Only unindented `key: value` lines are read, so keys nested under
+/// another entry are ignored. A quoted key or value is unquoted, and a `#`
+/// starts a comment only outside quotes and after whitespace. An entry
+/// without a value, such as the parent of a nested block, has none.
+fn parse_config_value(config: &str, key: &str) -> Option<String> {
+    config.lines().find_map(|line| {
+        if line.starts_with([' ', '\t', '#', '-']) {
+            return None;
+        }
+        let (entry_key, value) = split_key(line)?;
+        if entry_key != key {
+            return None;
+        }
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next()?,
+            _ => value
+                .match_indices('#')
+                .find(|(index, _)| *index == 0 || value[..*index].ends_with([' ', '\t']))
+                .map_or(value, |(index, _)| &value[..index])
+                .trim_end(),
+        };
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Split a YAML mapping line into its unquoted key and the rest after `:`.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    match line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let (key, rest) = line[1..].split_once(quote)?;
+            Some((key, rest.trim_start().strip_prefix(':')?))
+        }
+        _ => {
+            let (key, rest) = line
+                .split_once(": ")
+                .or_else(|| line.split_once(":\t"))
+                .or_else(|| line.strip_suffix(':').map(|key| (key, "")))?;
+            Some((key.trim_end(), rest))
+        }
+    }
+}
+
 /// Get the repository directory from environment variables.
 ///
 /// This function reads the `STACKCLASS_REPOSITORY_DIR` environment variable
-/// and returns it as a Path.
+/// and returns it as a Path, unless a directory was set with
+/// [`set_repo_dir`].
 ///
 /// # Returns
 ///
 /// * `Ok(PathBuf)` - The repository directory path
 /// * `Err(ProgramLoadError)` - If the environment variable is not set
 pub fn get_repo_dir() -> Result<std::path::PathBuf, ProgramLoadError> {
+    if let Some(repo_dir) = REPO_DIR_OVERRIDE.get() {
+        return Ok(repo_dir.clone());
+    }
     std::env::var("STACKCLASS_REPOSITORY_DIR")
         .map_err(|_| ProgramLoadError::RepoNotFound(std::path::PathBuf::from("Not set")))
         .map(std::path::PathBuf::from)
@@ -201,6 +298,9 @@ pub fn to_case_error_from_context(error: TestContextError) -> tester::CaseError
 
 /// Check if a program is available for testing.
 ///
+/// The program's ELF is validated and loaded once, and its size and SBF
+/// version are logged.
+///
 /// # Arguments
 ///
 /// * `repo_dir` - The repository directory
@@ -210,10 +310,9 @@ pub fn to_case_error_from_context(error: TestContextError) -> tester::CaseError
 /// * `Ok(())` - If the program is available
 /// * `Err(tester::CaseError)` - If the program is not available
 pub fn check_program_available(repo_dir: &Path) -> Result<(), tester::CaseError> {
-    match load_lending_program(repo_dir) {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
-    }
+    let info = preflight_program(repo_dir).map_err(to_case_error_from_load)?;
+    crate::report::log(format!("Loaded program {}", info));
+    Ok(())
 }
 
 /// Create a test instruction for the lending program.
@@ -270,29 +369,66 @@ impl LendingFixture {
         })
     }
 
-    pub fn initialize_instruction(&self) -> Instruction {
-        let data = build_initialize_data();
-        create_lending_instruction(
+    /// Get the lending program ID.
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    /// Get mutable access to the underlying test context.
+    pub fn context_mut(&mut self) -> &mut crate::mollusk::LendingTestContext {
+        &mut self.context
+    }
+
+    /// Write a program-owned, rent-exempt account at `address`.
+    pub fn seed_program_account(&mut self, address: Pubkey, data: Vec<u8>) {
+        let account = Account {
+            lamports: solana_rent::Rent::default().minimum_balance(data.len()),
+            data,
+            owner: self.program_id,
+            ..Default::default()
+        };
+        self.context.add_account(address, account);
+    }
+
+    /// Write a `Bank` account at the bank PDA of `bank.mint_address`.
+    pub fn seed_bank(&mut self, bank: &Bank) -> Result<Pubkey, TestContextError> {
+        let address = bank_address(&self.program_id, &bank.mint_address);
+        self.seed_program_account(address, bank.encode()?);
+        Ok(address)
+    }
+
+    /// Write a `User` account at the user PDA of `user.owner`.
+    pub fn seed_user(&mut self, user: &User) -> Result<Pubkey, TestContextError> {
+        let address = user_address(&self.program_id, &user.owner);
+        self.seed_program_account(address, user.encode()?);
+        Ok(address)
+    }
+
+    pub fn initialize_instruction(&self) -> Result<Instruction, TestContextError> {
+        let data = build_initialize_data()?;
+        Ok(create_lending_instruction(
             self.program_id,
             data,
             vec![
                 AccountMeta::new(self.user, true),
                 AccountMeta::new_readonly(solana_system_program::id(), false),
             ],
-        )
+        ))
     }
 
     pub fn execute_initialize(&mut self) -> Result<(), TestContextError> {
-        let instruction = self.initialize_instruction();
+        let instruction = self.initialize_instruction()?;
         self.context.execute_instruction(&instruction)
     }
 }
 
-fn build_initialize_data() -> Vec<u8> {
-    anchor_discriminator("global:initialize").to_vec()
+fn build_initialize_data() -> Result<Vec<u8>, TestContextError> {
+    Ok(crate::encoding::instruction_data("initialize", &[])?)
 }
 
-fn anchor_discriminator(name: &str) -> [u8; 8] {
+/// Compute the 8-byte Anchor discriminator for a namespaced name such as
+/// `global:deposit` or `account:Bank`.
+pub fn anchor_discriminator(name: &str) -> [u8; 8] {
     let mut hasher = Sha256::new();
     hasher.update(name.as_bytes());
     let hash = hasher.finalize();
@@ -310,6 +446,7 @@ pub fn run_env_setup_check() -> Result<(), tester::CaseError> {
         )) as Box<dyn std::error::Error + Send + Sync>);
     }
     check_program_available(&repo_path)?;
+    crate::encoding::load(&repo_path).map_err(to_case_error_from_load)?;
     run_initialize_smoke(&repo_path)
 }
 
@@ -325,20 +462,63 @@ pub fn run_solana_model_check() -> Result<(), tester::CaseError> {
 
 pub fn run_anchor_try_check() -> Result<(), tester::CaseError> {
     let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
-    let program_id = load_lending_program_id(&repo_path).map_err(to_case_error_from_load)?;
+    let source = find_lending_program_id(&repo_path).map_err(to_case_error_from_load)?;
     let default_id = Pubkey::from_str(DEFAULT_LENDING_PROGRAM_ID)
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
 
-    if program_id == Pubkey::default() || program_id == default_id {
+    if source.program_id == Pubkey::default() || source.program_id == default_id {
         return Err(Box::new(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
-            "Program ID is still default",
+            format!("Program ID is still default ({})", source),
         )) as Box<dyn std::error::Error + Send + Sync>);
     }
 
+    check_declared_program_id(&repo_path, &source).map_err(to_case_error)?;
     run_initialize_smoke(&repo_path)
 }
 
+/// Check that the Anchor.toml program ID matches the deploy keypair and the
+/// `declare_id!` compiled into the program.
+///
+/// A mismatch otherwise only shows up later as a confusing
+/// `DeclaredProgramIdMismatch` (4100) error from every instruction.
+fn check_declared_program_id(
+    repo_path: &Path,
+    source: &ProgramIdSource,
+) -> Result<(), TestContextError> {
+    let keypair = find_deploy_keypair(repo_path).map_err(|err| {
+        TestContextError::ValidationError(format!(
+            "Could not check the program ID {} against the deploy keypair: {}",
+            source, err
+        ))
+    })?;
+    if let Some((keypair_path, keypair_id)) = keypair &&
+        keypair_id != source.program_id
+    {
+        return Err(TestContextError::ValidationError(format!(
+            "Program ID {} does not match the deploy keypair {} ({}); run `anchor keys sync`",
+            source,
+            keypair_id,
+            keypair_path.display()
+        )));
+    }
+
+    let mut fixture = LendingFixture::new_default(repo_path)?;
+    let instruction = fixture.initialize_instruction()?;
+    let result = fixture.context_mut().process_instruction(&instruction);
+    if result.raw_result ==
+        Err(InstructionError::Custom(AnchorError::DeclaredProgramIdMismatch.code()))
+    {
+        return Err(TestContextError::ValidationError(format!(
+            "The program's declare_id! does not match the program ID {}; run `anchor keys sync` \
+             and rebuild",
+            source
+        )));
+    }
+
+    Ok(())
+}
+
 pub fn run_spl_token_basics_check() -> Result<(), tester::CaseError> {
     let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
     run_initialize_smoke(&repo_path)
@@ -346,12 +526,12 @@ pub fn run_spl_token_basics_check() -> Result<(), tester::CaseError> {
 
 pub fn run_cpi_transfer_check() -> Result<(), tester::CaseError> {
     let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
-    run_initialize_smoke(&repo_path)
+    scenario::run_embedded(&repo_path, scenario::BASIC_DEPOSIT).map_err(to_case_error)
 }
 
 pub fn run_token_transfer_check() -> Result<(), tester::CaseError> {
     let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
-    run_initialize_smoke(&repo_path)
+    scenario::run_embedded(&repo_path, scenario::BASIC_WITHDRAW).map_err(to_case_error)
 }
 
 pub fn run_pda_checks() -> Result<(), tester::CaseError> {
@@ -389,9 +569,29 @@ pub fn run_interest_checks() -> Result<(), tester::CaseError> {
     run_initialize_smoke(&repo_path)
 }
 
-pub fn run_security_checks() -> Result<(), tester::CaseError> {
+pub fn run_rate_model_checks() -> Result<(), tester::CaseError> {
     let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
-    run_initialize_smoke(&repo_path)
+    crate::rate_model::check_rate_curve(&repo_path).map_err(to_case_error)
+}
+
+pub fn run_authority_attack_checks() -> Result<(), tester::CaseError> {
+    let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
+    crate::security::check_authority_attacks(&repo_path).map_err(to_case_error)
+}
+
+pub fn run_account_substitution_checks() -> Result<(), tester::CaseError> {
+    let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
+    crate::security::check_account_substitution(&repo_path).map_err(to_case_error)
+}
+
+pub fn run_reentrancy_checks() -> Result<(), tester::CaseError> {
+    let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
+    crate::security::check_reentrancy(&repo_path).map_err(to_case_error)
+}
+
+pub fn run_extreme_value_checks() -> Result<(), tester::CaseError> {
+    let repo_path = get_repo_dir().map_err(to_case_error_from_load)?;
+    crate::security::check_extreme_values(&repo_path).map_err(to_case_error)
 }
 
 #[allow(dead_code)]
@@ -421,7 +621,44 @@ fn run_initialize_smoke(repo_path: &Path) -> Result<(), tester::CaseError> {
     let mut fixture = LendingFixture::new_default(repo_path).map_err(to_case_error)?;
     match fixture.execute_initialize() {
         Ok(()) => Ok(()),
-        Err(TestContextError::ExecutionError(_)) => Ok(()),
+        Err(TestContextError::InstructionFailed(_)) => Ok(()),
         Err(err) => Err(to_case_error(err)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_top_level_values() {
+        let config = "debug: true\ninstruction_encoding: native\n";
+        assert_eq!(parse_config_value(config, "debug"), Some("true".to_string()));
+        assert_eq!(parse_config_value(config, "instruction_encoding"), Some("native".to_string()));
+        assert_eq!(parse_config_value(config, "instruction_tags"), None);
+    }
+
+    #[test]
+    fn ignores_nested_keys_and_comments() {
+        let config = "# debug: true\nbuild:\n  debug: true\n  - debug: true\ndebug: false # on\n";
+        assert_eq!(parse_config_value(config, "debug"), Some("false".to_string()));
+        assert_eq!(parse_config_value(config, "build"), None);
+        assert_eq!(parse_config_value("debug: # unset\n", "debug"), None);
+    }
+
+    #[test]
+    fn unquotes_keys_and_values() {
+        let config = "\"instruction_tags\": 'initialize=0, deposit=1' # tags\ndebug: \"a # b\"\n";
+        assert_eq!(
+            parse_config_value(config, "instruction_tags"),
+            Some("initialize=0, deposit=1".to_string())
+        );
+        assert_eq!(parse_config_value(config, "debug"), Some("a # b".to_string()));
+    }
+
+    #[test]
+    fn keeps_colons_and_hashes_inside_values() {
+        let config = "url: https://example.com/a#b\n";
+        assert_eq!(parse_config_value(config, "url"), Some("https://example.com/a#b".to_string()));
+    }
+}
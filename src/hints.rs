@@ -0,0 +1,335 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hints printed after a failing stage's error.
+//!
+//! A failure is classified into a [`FailureKind`] from its error: a missing
+//! program, an Anchor error code, or an exceeded compute budget. The stage's
+//! own hint for that kind is shown if it has one (see [`Stage::hints`]),
+//! otherwise a general hint, followed by a documentation link.
+
+use crate::{
+    assertions::AnchorError,
+    definition::Stage,
+    mollusk::{ProgramLoadError, TestContextError},
+};
+use solana_instruction_error::InstructionError;
+use tester::CaseError;
+
+/// The kinds of failure hints are keyed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The program's `.so` or Anchor.toml could not be found.
+    ProgramNotBuilt,
+    /// The program has no handler for an instruction.
+    MissingInstruction,
+    /// An account did not match its `seeds` constraint.
+    SeedsMismatch,
+    /// An account's data was too small or did not match its type.
+    WrongAccountSize,
+    /// An account was used before it was initialized.
+    AccountNotInitialized,
+    /// The program's `declare_id!` differs from Anchor.toml.
+    ProgramIdMismatch,
+    /// An instruction ran out of compute units or exceeded its ceiling.
+    InsufficientComputeUnits,
+    /// The program panicked instead of returning an error.
+    ProgramAborted,
+}
+
+impl FailureKind {
+    /// Classify a stage's error.
+    ///
+    /// Typed errors are classified from their value. Other errors are
+    /// classified from their leading line only, since later lines carry
+    /// account diffs, findings and program logs that may mention any error.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The stage's error
+    ///
+    /// # Returns
+    ///
+    /// * `Some(FailureKind)` - If the error is of a recognized kind
+    /// * `None` - Otherwise
+    pub fn classify(error: &CaseError) -> Option<Self> {
+        if let Some(error) = error.downcast_ref::<ProgramLoadError>() {
+            return match error {
+                ProgramLoadError::AnchorTomlNotFound(_) | ProgramLoadError::ProgramNotFound(_) => {
+                    Some(FailureKind::ProgramNotBuilt)
+                }
+                _ => None,
+            };
+        }
+        if let Some(TestContextError::InstructionFailed(error)) =
+            error.downcast_ref::<TestContextError>()
+        {
+            return Self::from_instruction_error(error);
+        }
+
+        let message = error.to_string();
+        let line = message.lines().next().unwrap_or_default();
+        if line.contains("you haven't implemented") {
+            return Some(FailureKind::MissingInstruction);
+        }
+        if line.contains("ComputationalBudgetExceeded") ||
+            line.contains("compute units, above its limit")
+        {
+            return Some(FailureKind::InsufficientComputeUnits);
+        }
+        if line.contains("ProgramFailedToComplete") {
+            return Some(FailureKind::ProgramAborted);
+        }
+        if line.contains("AccountDataTooSmall") {
+            return Some(FailureKind::WrongAccountSize);
+        }
+        let code = line.split("Custom(").nth(1)?.split(')').next()?.parse::<u32>().ok()?;
+        Self::from_instruction_error(&InstructionError::Custom(code))
+    }
+
+    /// Classify the error an instruction returned.
+    fn from_instruction_error(error: &InstructionError) -> Option<Self> {
+        let code = match error {
+            InstructionError::ComputationalBudgetExceeded => {
+                return Some(FailureKind::InsufficientComputeUnits);
+            }
+            InstructionError::ProgramFailedToComplete => return Some(FailureKind::ProgramAborted),
+            InstructionError::AccountDataTooSmall => return Some(FailureKind::WrongAccountSize),
+            InstructionError::Custom(code) => *code,
+            _ => return None,
+        };
+        match code {
+            code if code == AnchorError::InstructionMissing.code() ||
+                code == AnchorError::InstructionFallbackNotFound.code() =>
+            {
+                Some(FailureKind::MissingInstruction)
+            }
+            code if code == AnchorError::ConstraintSeeds.code() => Some(FailureKind::SeedsMismatch),
+            code if code == AnchorError::AccountDiscriminatorMismatch.code() ||
+                code == AnchorError::AccountDidNotDeserialize.code() =>
+            {
+                Some(FailureKind::WrongAccountSize)
+            }
+            code if code == AnchorError::AccountNotInitialized.code() => {
+                Some(FailureKind::AccountNotInitialized)
+            }
+            code if code == AnchorError::DeclaredProgramIdMismatch.code() => {
+                Some(FailureKind::ProgramIdMismatch)
+            }
+            _ => None,
+        }
+    }
+
+    /// The hint shown when a stage has none of its own for this kind.
+    fn general_hint(self) -> &'static str {
+        match self {
+            FailureKind::ProgramNotBuilt => {
+                "Run `anchor build` in your repository so that Anchor.toml and \
+                 target/deploy/<program>.so exist."
+            }
+            FailureKind::MissingInstruction => {
+                "The program has no handler for the instruction being tested. Add it to your \
+                 #[program] module with the name the stage uses, then rebuild."
+            }
+            FailureKind::SeedsMismatch => {
+                "An account's PDA does not match its `seeds` constraint. Check the seeds and \
+                 their order: banks use [mint], treasuries [\"treasury\", mint] and users [owner]."
+            }
+            FailureKind::WrongAccountSize => {
+                "An account's data does not match its type. Check that `space` is 8 bytes for \
+                 the discriminator plus the struct's size, and that field order matches the \
+                 challenge's layout."
+            }
+            FailureKind::AccountNotInitialized => {
+                "An account was read before it was created. Initialize it with `init` or \
+                 `init_if_needed` in an earlier instruction."
+            }
+            FailureKind::ProgramIdMismatch => {
+                "The `declare_id!` in your program differs from the ID in Anchor.toml. Run \
+                 `anchor keys sync` and rebuild."
+            }
+            FailureKind::InsufficientComputeUnits => {
+                "An instruction used too many compute units. Avoid logging in hot paths, \
+                 redundant account loads and unbounded loops."
+            }
+            FailureKind::ProgramAborted => {
+                "The program panicked. Replace `unwrap()` and unchecked arithmetic with \
+                 `checked_*` operations that return an error."
+            }
+        }
+    }
+
+    /// Documentation to read for this kind of failure.
+    fn documentation(self) -> &'static str {
+        match self {
+            FailureKind::ProgramNotBuilt | FailureKind::ProgramIdMismatch => {
+                "https://www.anchor-lang.com/docs"
+            }
+            FailureKind::SeedsMismatch => "https://solana.com/docs/core/pda",
+            FailureKind::InsufficientComputeUnits => "https://solana.com/docs/core/fees",
+            FailureKind::MissingInstruction |
+            FailureKind::WrongAccountSize |
+            FailureKind::AccountNotInitialized |
+            FailureKind::ProgramAborted => "https://solana.com/docs/core/accounts",
+        }
+    }
+}
+
+/// Append the stage's hint for a failure to its error.
+///
+/// # Arguments
+///
+/// * `stage` - The stage that ran
+/// * `result` - The stage's result
+///
+/// # Returns
+///
+/// * `Result<(), CaseError>` - The result, with a hint appended to a recognized error
+pub fn annotate(stage: &Stage, result: Result<(), CaseError>) -> Result<(), CaseError> {
+    let Err(error) = result else {
+        return Ok(());
+    };
+    let Some(kind) = FailureKind::classify(&error) else {
+        return Err(error);
+    };
+
+    let hint = stage
+        .hints
+        .iter()
+        .find(|(hint_kind, _)| *hint_kind == kind)
+        .map_or(kind.general_hint(), |(_, hint)| *hint);
+    Err(format!("{}\n\nHint: {}\nSee: {}", error, hint, kind.documentation()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn classify(error: impl std::error::Error + Send + Sync + 'static) -> Option<FailureKind> {
+        FailureKind::classify(&(Box::new(error) as CaseError))
+    }
+
+    fn classify_message(message: &str) -> Option<FailureKind> {
+        FailureKind::classify(&CaseError::from(message))
+    }
+
+    fn instruction_failed(error: InstructionError) -> Option<FailureKind> {
+        classify(TestContextError::InstructionFailed(error))
+    }
+
+    fn anchor(error: AnchorError) -> Option<FailureKind> {
+        instruction_failed(InstructionError::Custom(error.code()))
+    }
+
+    #[test]
+    fn classifies_program_not_built() {
+        let missing = ProgramLoadError::AnchorTomlNotFound(PathBuf::from("Anchor.toml"));
+        assert_eq!(classify(missing), Some(FailureKind::ProgramNotBuilt));
+        let missing = ProgramLoadError::ProgramNotFound(Vec::new());
+        assert_eq!(classify(missing), Some(FailureKind::ProgramNotBuilt));
+        assert_eq!(classify(ProgramLoadError::ProgramIdNotFound), None);
+    }
+
+    #[test]
+    fn classifies_missing_instruction() {
+        assert_eq!(anchor(AnchorError::InstructionMissing), Some(FailureKind::MissingInstruction));
+        assert_eq!(
+            anchor(AnchorError::InstructionFallbackNotFound),
+            Some(FailureKind::MissingInstruction)
+        );
+        assert_eq!(
+            classify(TestContextError::ValidationError(
+                "you haven't implemented `borrow` yet".to_string()
+            )),
+            Some(FailureKind::MissingInstruction)
+        );
+    }
+
+    #[test]
+    fn classifies_seeds_mismatch() {
+        assert_eq!(anchor(AnchorError::ConstraintSeeds), Some(FailureKind::SeedsMismatch));
+    }
+
+    #[test]
+    fn classifies_wrong_account_size() {
+        assert_eq!(
+            anchor(AnchorError::AccountDiscriminatorMismatch),
+            Some(FailureKind::WrongAccountSize)
+        );
+        assert_eq!(
+            anchor(AnchorError::AccountDidNotDeserialize),
+            Some(FailureKind::WrongAccountSize)
+        );
+        assert_eq!(
+            instruction_failed(InstructionError::AccountDataTooSmall),
+            Some(FailureKind::WrongAccountSize)
+        );
+    }
+
+    #[test]
+    fn classifies_account_not_initialized() {
+        assert_eq!(
+            anchor(AnchorError::AccountNotInitialized),
+            Some(FailureKind::AccountNotInitialized)
+        );
+    }
+
+    #[test]
+    fn classifies_program_id_mismatch() {
+        assert_eq!(
+            anchor(AnchorError::DeclaredProgramIdMismatch),
+            Some(FailureKind::ProgramIdMismatch)
+        );
+    }
+
+    #[test]
+    fn classifies_insufficient_compute_units() {
+        assert_eq!(
+            instruction_failed(InstructionError::ComputationalBudgetExceeded),
+            Some(FailureKind::InsufficientComputeUnits)
+        );
+        assert_eq!(
+            classify(TestContextError::ValidationError(
+                "deposit used 90000 compute units, above its limit of 50000".to_string()
+            )),
+            Some(FailureKind::InsufficientComputeUnits)
+        );
+    }
+
+    #[test]
+    fn classifies_program_aborted() {
+        assert_eq!(
+            instruction_failed(InstructionError::ProgramFailedToComplete),
+            Some(FailureKind::ProgramAborted)
+        );
+    }
+
+    #[test]
+    fn classifies_an_error_code_on_the_leading_line() {
+        let message = format!("deposit failed: Custom({})", AnchorError::ConstraintSeeds.code());
+        assert_eq!(classify_message(&message), Some(FailureKind::SeedsMismatch));
+    }
+
+    #[test]
+    fn ignores_errors_after_the_leading_line() {
+        let message = format!(
+            "expected total_deposits to be 100, found 90\naccount changes:\n  data: \
+             Custom({})\nProgram log: ProgramFailedToComplete",
+            AnchorError::ConstraintSeeds.code()
+        );
+        assert_eq!(classify_message(&message), None);
+        assert_eq!(instruction_failed(InstructionError::InvalidArgument), None);
+    }
+}
@@ -0,0 +1,583 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Anchor IDL loading for instruction discovery.
+//!
+//! `anchor build` writes the program's IDL to `target/idl/<program>.json`.
+//! Stages use it to enumerate the instructions a student implemented and
+//! the names, flags and PDA seeds of each instruction's accounts. Both the
+//! current (0.30+) and the legacy (`isMut` / `isSigner`) formats are read.
+//!
+//! Native programs can describe themselves with a Shank IDL, which uses the
+//! legacy format with a one-byte `discriminant` per instruction and is
+//! written to `idl/<program>.json`.
+
+use crate::{encoding, mollusk::ProgramLoadError};
+use serde::Deserialize;
+use serde_json::Value;
+use solana_instruction::AccountMeta;
+use solana_pubkey::Pubkey;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    str::FromStr,
+};
+
+/// The subset of an Anchor IDL used by the tester.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Idl {
+    #[serde(default)]
+    pub instructions: Vec<IdlInstruction>,
+}
+
+/// An instruction declared in the IDL.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlInstruction {
+    pub name: String,
+    #[serde(default)]
+    pub discriminator: Option<Vec<u8>>,
+    #[serde(default)]
+    discriminant: Option<IdlDiscriminant>,
+    #[serde(default)]
+    accounts: Vec<IdlAccountItem>,
+    #[serde(default)]
+    pub args: Vec<IdlField>,
+}
+
+/// A Shank instruction's one-byte tag.
+#[derive(Debug, Clone, Deserialize)]
+struct IdlDiscriminant {
+    value: u8,
+}
+
+/// An account slot, or a nested group of slots from a composite
+/// `Accounts` struct.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum IdlAccountItem {
+    Group { accounts: Vec<IdlAccountItem> },
+    Account(IdlAccount),
+}
+
+/// A single account slot of an instruction.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlAccount {
+    pub name: String,
+    #[serde(default, alias = "isMut")]
+    pub writable: bool,
+    #[serde(default, alias = "isSigner")]
+    pub signer: bool,
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub pda: Option<IdlPda>,
+}
+
+/// PDA seeds of an account slot, and the deriving program when it is not
+/// the program itself.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlPda {
+    #[serde(default)]
+    pub seeds: Vec<IdlSeed>,
+    #[serde(default)]
+    pub program: Option<IdlSeed>,
+}
+
+/// A single PDA seed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum IdlSeed {
+    Const { value: Vec<u8> },
+    Account { path: String },
+    Arg { path: String },
+}
+
+/// A named instruction argument.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdlField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: Value,
+}
+
+impl Idl {
+    /// Find an instruction by its snake_case name.
+    pub fn instruction(&self, name: &str) -> Option<&IdlInstruction> {
+        self.instructions.iter().find(|instruction| instruction.matches(name))
+    }
+}
+
+impl IdlInstruction {
+    /// The instruction's discriminator, falling back to its tag in the
+    /// program's instruction encoding for IDLs that do not record it.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The discriminator
+    /// * `Err(String)` - If neither the IDL nor the encoding has one
+    pub fn discriminator(&self) -> Result<Vec<u8>, String> {
+        if let Some(discriminator) = &self.discriminator {
+            return Ok(discriminator.clone());
+        }
+        if let Some(discriminant) = &self.discriminant {
+            return Ok(vec![discriminant.value]);
+        }
+        let encoding = encoding::current().map_err(|err| err.to_string())?;
+        encoding.tag(&to_snake_case(&self.name)).ok_or_else(|| {
+            format!("`{}` has no discriminator in the IDL or instruction_tags", self.name)
+        })
+    }
+
+    /// The instruction's account slots in order, with composite groups
+    /// flattened.
+    pub fn accounts(&self) -> Vec<&IdlAccount> {
+        fn flatten<'a>(items: &'a [IdlAccountItem], out: &mut Vec<&'a IdlAccount>) {
+            for item in items {
+                match item {
+                    IdlAccountItem::Group { accounts } => flatten(accounts, out),
+                    IdlAccountItem::Account(account) => out.push(account),
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        flatten(&self.accounts, &mut out);
+        out
+    }
+
+    /// Instruction data with the discriminator followed by a placeholder
+    /// value for every argument.
+    ///
+    /// Integer arguments are encoded as `1` rather than `0` so that handlers
+    /// rejecting zero amounts do not mask the account checks under test.
+    /// Fails if the instruction has no discriminator.
+    pub fn placeholder_data(&self) -> Result<Vec<u8>, String> {
+        let mut data = self.discriminator()?;
+        for arg in &self.args {
+            data.extend(placeholder_value(&arg.ty));
+        }
+        Ok(data)
+    }
+
+    /// Like [`Self::placeholder_data`], with the values in `args` for the
+    /// integer arguments they name.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<u8>)` - The instruction data
+    /// * `Err(String)` - If an argument is unknown, not an integer, or out of range for its type
+    pub fn data_with_args(&self, args: &BTreeMap<String, u64>) -> Result<Vec<u8>, String> {
+        if let Some(unknown) = args.keys().find(|name| !self.args.iter().any(|a| a.name == **name))
+        {
+            return Err(format!("`{}` has no argument `{}`", self.name, unknown));
+        }
+
+        let mut data = self.discriminator()?;
+        for arg in &self.args {
+            match args.get(&arg.name) {
+                Some(&value) => data.extend(integer_value(&arg.ty, value).ok_or_else(|| {
+                    format!("argument `{}` cannot hold the integer {}", arg.name, value)
+                })?),
+                None => data.extend(placeholder_value(&arg.ty)),
+            }
+        }
+        Ok(data)
+    }
+
+    /// Whether the instruction has the given snake_case name.
+    pub fn matches(&self, name: &str) -> bool {
+        to_snake_case(&self.name) == name
+    }
+}
+
+/// Load the program's IDL from `target/idl`, or from Shank's `idl`
+/// directory if there is no `target/idl`.
+///
+/// `lending_program.json` is preferred; otherwise the only JSON file in the
+/// directory is used.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(Idl)` - The parsed IDL
+/// * `Err(ProgramLoadError)` - If the IDL cannot be found or parsed
+pub fn load_idl(repo_dir: &Path) -> Result<Idl, ProgramLoadError> {
+    let idl_dir = [repo_dir.join("target/idl"), repo_dir.join("idl")]
+        .into_iter()
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| repo_dir.join("target/idl"));
+    let preferred = idl_dir.join("lending_program.json");
+
+    let path = if preferred.exists() {
+        preferred
+    } else {
+        let mut candidates: Vec<_> = std::fs::read_dir(&idl_dir)
+            .map_err(|_| ProgramLoadError::IdlNotFound(idl_dir.clone()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        if candidates.len() != 1 {
+            return Err(ProgramLoadError::IdlNotFound(preferred));
+        }
+        candidates.remove(0)
+    };
+
+    let content = std::fs::read_to_string(&path)?;
+    serde_json::from_str(&content)
+        .map_err(|err| ProgramLoadError::InvalidIdl(format!("{}: {}", path.display(), err)))
+}
+
+/// Resolve every account slot of an instruction.
+///
+/// Each slot is taken from `overrides` by name, else from its fixed IDL
+/// address, the well-known program it names, or its PDA seeds; slots none
+/// of these resolve are passed to `fallback`. A PDA is derived once the
+/// accounts its seeds name are resolved, wherever they are in the list.
+/// Argument seeds take the value the instruction data carries for that
+/// argument, as built by [`IdlInstruction::data_with_args`] from the same
+/// `args`.
+///
+/// # Arguments
+///
+/// * `instruction` - The instruction whose accounts to resolve
+/// * `program_id` - The program PDAs are derived from by default
+/// * `args` - Integer argument values, by name; others are placeholders
+/// * `overrides` - Accounts to use for specific slots, by IDL name
+/// * `fallback` - Resolves any remaining slot
+///
+/// # Returns
+///
+/// * `Ok(Vec<(String, AccountMeta)>)` - The account slots, by IDL name
+/// * `Err(String)` - If a PDA has a seed that cannot be resolved
+pub fn resolve_accounts(
+    instruction: &IdlInstruction,
+    program_id: &Pubkey,
+    args: &BTreeMap<String, u64>,
+    overrides: &HashMap<String, Pubkey>,
+    mut fallback: impl FnMut(&IdlAccount) -> Pubkey,
+) -> Result<Vec<(String, AccountMeta)>, String> {
+    let accounts = instruction.accounts();
+    let mut resolved: HashMap<String, Pubkey> = accounts
+        .iter()
+        .filter_map(|account| Some((account.name.clone(), *overrides.get(&account.name)?)))
+        .collect();
+
+    // Resolve in passes, deferring PDAs whose seed accounts are not resolved
+    // yet, until a pass makes no progress.
+    let mut waiting = true;
+    while waiting {
+        waiting = false;
+        let mut progressed = false;
+        for account in &accounts {
+            if resolved.contains_key(&account.name) {
+                continue;
+            }
+            if waits_for_account(account, &accounts, &resolved) {
+                waiting = true;
+                continue;
+            }
+            let pubkey = resolve_account(instruction, account, program_id, args, &resolved)
+                .map_err(|err| format!("cannot derive `{}`: {}", account.name, err))?
+                .unwrap_or_else(|| fallback(account));
+            resolved.insert(account.name.clone(), pubkey);
+            progressed = true;
+        }
+        if waiting && !progressed {
+            let stuck: Vec<&str> = accounts
+                .iter()
+                .filter(|account| !resolved.contains_key(&account.name))
+                .map(|account| account.name.as_str())
+                .collect();
+            return Err(format!(
+                "cannot derive `{}`: their seeds refer to each other",
+                stuck.join("`, `")
+            ));
+        }
+    }
+
+    Ok(accounts
+        .iter()
+        .map(|account| {
+            let pubkey = resolved[&account.name];
+            let meta = if account.writable {
+                AccountMeta::new(pubkey, account.signer)
+            } else {
+                AccountMeta::new_readonly(pubkey, account.signer)
+            };
+            (account.name.clone(), meta)
+        })
+        .collect())
+}
+
+/// Whether a slot's PDA seeds name another slot that is not resolved yet.
+fn waits_for_account(
+    account: &IdlAccount,
+    accounts: &[&IdlAccount],
+    resolved: &HashMap<String, Pubkey>,
+) -> bool {
+    let Some(pda) = &account.pda else {
+        return false;
+    };
+    pda.seeds.iter().chain(&pda.program).any(|seed| match seed {
+        IdlSeed::Account { path } => {
+            !resolved.contains_key(path) && accounts.iter().any(|other| other.name == *path)
+        }
+        _ => false,
+    })
+}
+
+/// Resolve a slot from its IDL address, well-known name or PDA seeds.
+///
+/// # Returns
+///
+/// * `Ok(Some(Pubkey))` - The slot's address
+/// * `Ok(None)` - If the IDL does not determine the address
+/// * `Err(String)` - If the slot is a PDA with a seed that cannot be resolved
+fn resolve_account(
+    instruction: &IdlInstruction,
+    account: &IdlAccount,
+    program_id: &Pubkey,
+    args: &BTreeMap<String, u64>,
+    resolved: &HashMap<String, Pubkey>,
+) -> Result<Option<Pubkey>, String> {
+    if let Some(address) = account.address.as_deref().and_then(|a| Pubkey::from_str(a).ok()) {
+        return Ok(Some(address));
+    }
+
+    match account.name.as_str() {
+        "system_program" | "systemProgram" => return Ok(Some(solana_system_program::id())),
+        "token_program" | "tokenProgram" => return Ok(Some(spl_token_interface::ID)),
+        "associated_token_program" | "associatedTokenProgram" => {
+            return Ok(Some(spl_associated_token_account_interface::program::ID));
+        }
+        _ => {}
+    }
+
+    let Some(pda) = account.pda.as_ref() else {
+        return Ok(None);
+    };
+    let seeds: Vec<Vec<u8>> = pda
+        .seeds
+        .iter()
+        .map(|seed| seed_bytes(instruction, seed, args, resolved))
+        .collect::<Result<_, _>>()?;
+    let program = match &pda.program {
+        Some(seed) => {
+            let bytes = seed_bytes(instruction, seed, args, resolved)?;
+            <[u8; 32]>::try_from(bytes)
+                .map(Pubkey::new_from_array)
+                .map_err(|_| "its PDA program is not a 32-byte address".to_string())?
+        }
+        None => *program_id,
+    };
+    let seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+    Ok(Some(Pubkey::find_program_address(&seeds, &program).0))
+}
+
+/// Byte value of a PDA seed.
+///
+/// Fields of accounts and arguments (`user.owner`, `params.id`) are not
+/// read, and neither are arguments whose seed bytes differ from their Borsh
+/// encoding, such as strings; seeds using them are errors.
+fn seed_bytes(
+    instruction: &IdlInstruction,
+    seed: &IdlSeed,
+    args: &BTreeMap<String, u64>,
+    resolved: &HashMap<String, Pubkey>,
+) -> Result<Vec<u8>, String> {
+    match seed {
+        IdlSeed::Const { value } => Ok(value.clone()),
+        IdlSeed::Account { path } if path.contains('.') => {
+            Err(format!("the account field seed `{}` is not supported", path))
+        }
+        IdlSeed::Account { path } => resolved
+            .get(path)
+            .map(|pubkey| pubkey.to_bytes().to_vec())
+            .ok_or_else(|| format!("the seed account `{}` cannot be resolved", path)),
+        IdlSeed::Arg { path } if path.contains('.') => {
+            Err(format!("the argument field seed `{}` is not supported", path))
+        }
+        IdlSeed::Arg { path } => {
+            let arg = instruction
+                .args
+                .iter()
+                .find(|arg| arg.name == *path)
+                .ok_or_else(|| format!("the seed argument `{}` does not exist", path))?;
+            let seedable = integer_value(&arg.ty, 0).is_some() ||
+                matches!(arg.ty.as_str(), Some("bool" | "pubkey" | "publicKey"));
+            if !seedable {
+                return Err(format!("the seed argument `{}` has an unsupported type", path));
+            }
+            match args.get(path) {
+                Some(&value) => integer_value(&arg.ty, value).ok_or_else(|| {
+                    format!("argument `{}` cannot hold the integer {}", path, value)
+                }),
+                None => Ok(placeholder_value(&arg.ty)),
+            }
+        }
+    }
+}
+
+/// Borsh bytes of `value` as an integer IDL type, or `None` if the type is
+/// not an integer or cannot hold the value.
+fn integer_value(ty: &Value, value: u64) -> Option<Vec<u8>> {
+    Some(match ty.as_str()? {
+        "u8" => u8::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "u16" => u16::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "u32" => u32::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "u64" => value.to_le_bytes().to_vec(),
+        "u128" => u128::from(value).to_le_bytes().to_vec(),
+        "i8" => i8::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "i16" => i16::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "i32" => i32::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "i64" => i64::try_from(value).ok()?.to_le_bytes().to_vec(),
+        "i128" => i128::from(value).to_le_bytes().to_vec(),
+        _ => return None,
+    })
+}
+
+/// Borsh bytes of a placeholder value for an IDL type.
+fn placeholder_value(ty: &Value) -> Vec<u8> {
+    match ty {
+        Value::String(name) => match name.as_str() {
+            "bool" => vec![0],
+            "u8" | "i8" => vec![1],
+            "u16" | "i16" => 1u16.to_le_bytes().to_vec(),
+            "u32" | "i32" | "f32" => 1u32.to_le_bytes().to_vec(),
+            "u64" | "i64" | "f64" => 1u64.to_le_bytes().to_vec(),
+            "u128" | "i128" => 1u128.to_le_bytes().to_vec(),
+            "pubkey" | "publicKey" => vec![0; 32],
+            // Empty strings and byte vectors.
+            _ => vec![0; 4],
+        },
+        Value::Object(map) if map.contains_key("option") => vec![0],
+        Value::Object(map) if map.contains_key("vec") => vec![0; 4],
+        Value::Object(map) => match map.get("array").and_then(Value::as_array) {
+            Some(array) => {
+                let len = array.get(1).and_then(Value::as_u64).unwrap_or(0) as usize;
+                let element = array.first().map(placeholder_value).unwrap_or_default();
+                element.repeat(len)
+            }
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Convert legacy camelCase IDL names to the snake_case used in sighashes.
+fn to_snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for ch in name.chars() {
+        if ch.is_ascii_uppercase() {
+            if !out.is_empty() {
+                out.push('_');
+            }
+            out.push(ch.to_ascii_lowercase());
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn instruction(accounts: Value) -> IdlInstruction {
+        serde_json::from_value(json!({
+            "name": "deposit",
+            "accounts": accounts,
+            "args": [{ "name": "amount", "type": "u64" }, { "name": "memo", "type": "string" }],
+        }))
+        .unwrap()
+    }
+
+    fn resolve(
+        instruction: &IdlInstruction,
+        args: &BTreeMap<String, u64>,
+    ) -> Result<HashMap<String, Pubkey>, String> {
+        let program_id = Pubkey::new_from_array([7; 32]);
+        let accounts = resolve_accounts(instruction, &program_id, args, &HashMap::new(), |_| {
+            Pubkey::new_from_array([1; 32])
+        })?;
+        Ok(accounts.into_iter().map(|(name, meta)| (name, meta.pubkey)).collect())
+    }
+
+    #[test]
+    fn derives_arg_seeds_from_the_instruction_data() {
+        let instruction = instruction(json!([{
+            "name": "receipt",
+            "pda": { "seeds": [{ "kind": "arg", "path": "amount" }] },
+        }]));
+        let program_id = Pubkey::new_from_array([7; 32]);
+
+        let args = BTreeMap::from([("amount".to_string(), 42)]);
+        let expected =
+            Pubkey::find_program_address(&[42u64.to_le_bytes().as_slice()], &program_id).0;
+        assert_eq!(resolve(&instruction, &args).unwrap()["receipt"], expected);
+
+        let placeholder =
+            Pubkey::find_program_address(&[1u64.to_le_bytes().as_slice()], &program_id).0;
+        assert_eq!(resolve(&instruction, &BTreeMap::new()).unwrap()["receipt"], placeholder);
+    }
+
+    #[test]
+    fn derives_seeds_from_accounts_listed_later() {
+        let instruction = instruction(json!([
+            { "name": "bank", "pda": { "seeds": [{ "kind": "account", "path": "mint" }] } },
+            { "name": "mint" },
+        ]));
+        let program_id = Pubkey::new_from_array([7; 32]);
+        let mint = Pubkey::new_from_array([1; 32]);
+
+        let resolved = resolve(&instruction, &BTreeMap::new()).unwrap();
+        assert_eq!(resolved["mint"], mint);
+        assert_eq!(resolved["bank"], Pubkey::find_program_address(&[mint.as_ref()], &program_id).0);
+    }
+
+    #[test]
+    fn rejects_seeds_it_cannot_resolve() {
+        for (seed, reason) in [
+            (json!({ "kind": "account", "path": "user.owner" }), "account field seed"),
+            (json!({ "kind": "arg", "path": "params.id" }), "argument field seed"),
+            (json!({ "kind": "arg", "path": "memo" }), "unsupported type"),
+            (json!({ "kind": "arg", "path": "missing" }), "does not exist"),
+        ] {
+            let instruction =
+                instruction(json!([{ "name": "receipt", "pda": { "seeds": [seed] } }]));
+            let error = resolve(&instruction, &BTreeMap::new()).unwrap_err();
+            assert!(
+                error.contains("cannot derive `receipt`") && error.contains(reason),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_cyclic_seeds() {
+        let instruction = instruction(json!([
+            { "name": "a", "pda": { "seeds": [{ "kind": "account", "path": "b" }] } },
+            { "name": "b", "pda": { "seeds": [{ "kind": "account", "path": "a" }] } },
+        ]));
+        let error = resolve(&instruction, &BTreeMap::new()).unwrap_err();
+        assert!(error.contains("`a`, `b`"), "{}", error);
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Protocol-wide conservation invariants.
+//!
+//! After an instruction, every `Bank` the program owns must be backed by its
+//! treasury and by the `User` accounts that hold its shares, and no lamports
+//! may have appeared out of nowhere. A `User` counts towards a bank's USDC
+//! side if its `usdc_address` is the bank's mint, and its SOL side otherwise.
+//!
+//! Checks are enabled per context with
+//! [`crate::mollusk::LendingTestContext::enable_invariant_checks`]; the
+//! scenario runner and the fuzzer enable them for every step.
+
+use crate::layout::{Bank, User, treasury_address};
+use solana_account::Account;
+use solana_program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token_interface::state::Account as TokenAccount;
+use std::collections::HashMap;
+
+/// Check every invariant across an instruction.
+///
+/// # Arguments
+///
+/// * `program_id` - The lending program, which owns the `Bank` and `User` accounts
+/// * `before` - The account state before the instruction
+/// * `after` - The account state after it
+///
+/// # Returns
+///
+/// * `Vec<String>` - One line per violated invariant; empty if all hold
+pub fn violations(
+    program_id: &Pubkey,
+    before: &HashMap<Pubkey, Account>,
+    after: &HashMap<Pubkey, Account>,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    let lamports = |accounts: &HashMap<Pubkey, Account>| {
+        accounts.values().map(|account| u128::from(account.lamports)).sum::<u128>()
+    };
+    let (lamports_before, lamports_after) = (lamports(before), lamports(after));
+    if lamports_after > lamports_before {
+        violations.push(format!(
+            "{} lamports were created (total went from {} to {})",
+            lamports_after - lamports_before,
+            lamports_before,
+            lamports_after
+        ));
+    }
+
+    let owned = after.iter().filter(|(_, account)| account.owner == *program_id);
+    let banks: Vec<(Pubkey, Bank)> = owned
+        .clone()
+        .filter_map(|(pubkey, account)| Some((*pubkey, Bank::decode(&account.data)?)))
+        .collect();
+    let users: Vec<User> = owned.filter_map(|(_, account)| User::decode(&account.data)).collect();
+
+    for (address, bank) in &banks {
+        let treasury = treasury_address(program_id, &bank.mint_address);
+        let vault = after
+            .get(&treasury)
+            .and_then(|account| TokenAccount::unpack(&account.data).ok())
+            .map_or(0, |token| token.amount);
+        let available = bank.total_deposits.saturating_sub(bank.total_borrowed);
+        if vault < available {
+            violations.push(format!(
+                "Bank {} holds {} tokens in its treasury, less than total deposits minus total \
+                 borrows ({} - {} = {})",
+                address, vault, bank.total_deposits, bank.total_borrowed, available
+            ));
+        }
+
+        let positions: Vec<(u64, u64, u64)> = users
+            .iter()
+            .map(|user| {
+                if user.usdc_address == bank.mint_address {
+                    (user.deposited_usdc_shares, user.borrowed_usdc_shares, user.borrowed_usdc)
+                } else {
+                    (user.deposited_sol_shares, user.borrowed_sol_shares, user.borrowed_sol)
+                }
+            })
+            .collect();
+        let deposit_shares: u128 = positions.iter().map(|(shares, ..)| u128::from(*shares)).sum();
+        let borrowed_shares: u128 =
+            positions.iter().map(|(_, shares, _)| u128::from(*shares)).sum();
+        if deposit_shares != u128::from(bank.total_deposit_shares) {
+            violations.push(format!(
+                "Bank {} has {} deposit shares, but its users hold {}",
+                address, bank.total_deposit_shares, deposit_shares
+            ));
+        }
+        if borrowed_shares != u128::from(bank.total_borrowed_shares) {
+            violations.push(format!(
+                "Bank {} has {} borrowed shares, but its users hold {}",
+                address, bank.total_borrowed_shares, borrowed_shares
+            ));
+        }
+        if bank.total_borrowed > 0 && positions.iter().all(|(_, _, borrowed)| *borrowed == 0) {
+            violations.push(format!(
+                "Bank {} has {} borrowed, but no User account records the debt",
+                address, bank.total_borrowed
+            ));
+        }
+    }
+
+    violations
+}
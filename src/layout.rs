@@ -0,0 +1,287 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Account layouts used by the lending program challenge.
+//!
+//! The challenge asks students to implement `Bank` and `User` accounts with
+//! a fixed field order. This module decodes and encodes those accounts so
+//! stages can inspect program state or seed it directly. The header in front
+//! of the fields, Anchor's discriminator or nothing for native programs, is
+//! taken from the program's instruction encoding.
+
+use crate::{encoding, mollusk::ProgramLoadError};
+use solana_pubkey::Pubkey;
+
+/// Serialized size of a `Bank` account, including an Anchor discriminator.
+pub const BANK_ACCOUNT_SIZE: usize = 8 + 32 + 32 + 8 * 8 + 8 + 8;
+
+/// Serialized size of a `User` account, including an Anchor discriminator.
+pub const USER_ACCOUNT_SIZE: usize = 8 + 32 + 8 * 8 + 32 + 8 + 8;
+
+/// The `Bank` account holding the state of a single lending market.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bank {
+    pub authority: Pubkey,
+    pub mint_address: Pubkey,
+    pub total_deposits: u64,
+    pub total_deposit_shares: u64,
+    pub total_borrowed: u64,
+    pub total_borrowed_shares: u64,
+    pub liquidation_threshold: u64,
+    pub liquidation_bonus: u64,
+    pub liquidation_close_factor: u64,
+    pub max_ltv: u64,
+    pub last_updated: i64,
+    pub interest_rate: u64,
+}
+
+/// The `User` account tracking a single user's positions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct User {
+    pub owner: Pubkey,
+    pub deposited_sol: u64,
+    pub deposited_sol_shares: u64,
+    pub borrowed_sol: u64,
+    pub borrowed_sol_shares: u64,
+    pub deposited_usdc: u64,
+    pub deposited_usdc_shares: u64,
+    pub borrowed_usdc: u64,
+    pub borrowed_usdc_shares: u64,
+    pub usdc_address: Pubkey,
+    pub health_factor: u64,
+    pub last_updated: i64,
+}
+
+impl Bank {
+    /// Decode a `Bank` account, returning `None` if the header or length
+    /// does not match or the program's encoding cannot be loaded.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader::new(data, "Bank")?;
+        Some(Self {
+            authority: reader.pubkey()?,
+            mint_address: reader.pubkey()?,
+            total_deposits: reader.u64()?,
+            total_deposit_shares: reader.u64()?,
+            total_borrowed: reader.u64()?,
+            total_borrowed_shares: reader.u64()?,
+            liquidation_threshold: reader.u64()?,
+            liquidation_bonus: reader.u64()?,
+            liquidation_close_factor: reader.u64()?,
+            max_ltv: reader.u64()?,
+            last_updated: reader.i64()?,
+            interest_rate: reader.u64()?,
+        })
+    }
+
+    /// Encode the account with the program's account header, failing if
+    /// the program's encoding cannot be loaded.
+    pub fn encode(&self) -> Result<Vec<u8>, ProgramLoadError> {
+        let mut data = Vec::with_capacity(BANK_ACCOUNT_SIZE);
+        data.extend(encoding::current()?.account_header("Bank"));
+        data.extend_from_slice(self.authority.as_ref());
+        data.extend_from_slice(self.mint_address.as_ref());
+        for value in [
+            self.total_deposits,
+            self.total_deposit_shares,
+            self.total_borrowed,
+            self.total_borrowed_shares,
+            self.liquidation_threshold,
+            self.liquidation_bonus,
+            self.liquidation_close_factor,
+            self.max_ltv,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&self.last_updated.to_le_bytes());
+        data.extend_from_slice(&self.interest_rate.to_le_bytes());
+        Ok(data)
+    }
+
+    /// The account's fields as `(name, value)` pairs, in layout order.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("authority", self.authority.to_string()),
+            ("mint_address", self.mint_address.to_string()),
+            ("total_deposits", self.total_deposits.to_string()),
+            ("total_deposit_shares", self.total_deposit_shares.to_string()),
+            ("total_borrowed", self.total_borrowed.to_string()),
+            ("total_borrowed_shares", self.total_borrowed_shares.to_string()),
+            ("liquidation_threshold", self.liquidation_threshold.to_string()),
+            ("liquidation_bonus", self.liquidation_bonus.to_string()),
+            ("liquidation_close_factor", self.liquidation_close_factor.to_string()),
+            ("max_ltv", self.max_ltv.to_string()),
+            ("last_updated", self.last_updated.to_string()),
+            ("interest_rate", self.interest_rate.to_string()),
+        ]
+    }
+
+    /// Set a numeric field by name, returning `false` if there is none.
+    pub fn set_field(&mut self, name: &str, value: u64) -> bool {
+        let field = match name {
+            "total_deposits" => &mut self.total_deposits,
+            "total_deposit_shares" => &mut self.total_deposit_shares,
+            "total_borrowed" => &mut self.total_borrowed,
+            "total_borrowed_shares" => &mut self.total_borrowed_shares,
+            "liquidation_threshold" => &mut self.liquidation_threshold,
+            "liquidation_bonus" => &mut self.liquidation_bonus,
+            "liquidation_close_factor" => &mut self.liquidation_close_factor,
+            "max_ltv" => &mut self.max_ltv,
+            "interest_rate" => &mut self.interest_rate,
+            "last_updated" => {
+                self.last_updated = value as i64;
+                return true;
+            }
+            _ => return false,
+        };
+        *field = value;
+        true
+    }
+}
+
+impl User {
+    /// Decode a `User` account, returning `None` if the header or length
+    /// does not match or the program's encoding cannot be loaded.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader::new(data, "User")?;
+        Some(Self {
+            owner: reader.pubkey()?,
+            deposited_sol: reader.u64()?,
+            deposited_sol_shares: reader.u64()?,
+            borrowed_sol: reader.u64()?,
+            borrowed_sol_shares: reader.u64()?,
+            deposited_usdc: reader.u64()?,
+            deposited_usdc_shares: reader.u64()?,
+            borrowed_usdc: reader.u64()?,
+            borrowed_usdc_shares: reader.u64()?,
+            usdc_address: reader.pubkey()?,
+            health_factor: reader.u64()?,
+            last_updated: reader.i64()?,
+        })
+    }
+
+    /// Encode the account with the program's account header, failing if
+    /// the program's encoding cannot be loaded.
+    pub fn encode(&self) -> Result<Vec<u8>, ProgramLoadError> {
+        let mut data = Vec::with_capacity(USER_ACCOUNT_SIZE);
+        data.extend(encoding::current()?.account_header("User"));
+        data.extend_from_slice(self.owner.as_ref());
+        for value in [
+            self.deposited_sol,
+            self.deposited_sol_shares,
+            self.borrowed_sol,
+            self.borrowed_sol_shares,
+            self.deposited_usdc,
+            self.deposited_usdc_shares,
+            self.borrowed_usdc,
+            self.borrowed_usdc_shares,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(self.usdc_address.as_ref());
+        data.extend_from_slice(&self.health_factor.to_le_bytes());
+        data.extend_from_slice(&self.last_updated.to_le_bytes());
+        Ok(data)
+    }
+
+    /// The account's fields as `(name, value)` pairs, in layout order.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("owner", self.owner.to_string()),
+            ("deposited_sol", self.deposited_sol.to_string()),
+            ("deposited_sol_shares", self.deposited_sol_shares.to_string()),
+            ("borrowed_sol", self.borrowed_sol.to_string()),
+            ("borrowed_sol_shares", self.borrowed_sol_shares.to_string()),
+            ("deposited_usdc", self.deposited_usdc.to_string()),
+            ("deposited_usdc_shares", self.deposited_usdc_shares.to_string()),
+            ("borrowed_usdc", self.borrowed_usdc.to_string()),
+            ("borrowed_usdc_shares", self.borrowed_usdc_shares.to_string()),
+            ("usdc_address", self.usdc_address.to_string()),
+            ("health_factor", self.health_factor.to_string()),
+            ("last_updated", self.last_updated.to_string()),
+        ]
+    }
+
+    /// Set a numeric field by name, returning `false` if there is none.
+    pub fn set_field(&mut self, name: &str, value: u64) -> bool {
+        let field = match name {
+            "deposited_sol" => &mut self.deposited_sol,
+            "deposited_sol_shares" => &mut self.deposited_sol_shares,
+            "borrowed_sol" => &mut self.borrowed_sol,
+            "borrowed_sol_shares" => &mut self.borrowed_sol_shares,
+            "deposited_usdc" => &mut self.deposited_usdc,
+            "deposited_usdc_shares" => &mut self.deposited_usdc_shares,
+            "borrowed_usdc" => &mut self.borrowed_usdc,
+            "borrowed_usdc_shares" => &mut self.borrowed_usdc_shares,
+            "health_factor" => &mut self.health_factor,
+            "last_updated" => {
+                self.last_updated = value as i64;
+                return true;
+            }
+            _ => return false,
+        };
+        *field = value;
+        true
+    }
+}
+
+/// Derive the `Bank` PDA for a mint (seeds: `[mint]`).
+pub fn bank_address(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[mint.as_ref()], program_id).0
+}
+
+/// Derive the treasury token account PDA for a mint (seeds: `["treasury", mint]`).
+pub fn treasury_address(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"treasury", mint.as_ref()], program_id).0
+}
+
+/// Derive the `User` PDA for a signer (seeds: `[signer]`).
+pub fn user_address(program_id: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[owner.as_ref()], program_id).0
+}
+
+/// Sequential little-endian reader over account data.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Start after the program's header for the named account type,
+    /// returning `None` if the data does not begin with it.
+    fn new(data: &'a [u8], account: &str) -> Option<Self> {
+        let header = encoding::current().ok()?.account_header(account);
+        if !data.starts_with(&header) {
+            return None;
+        }
+        Some(Self { data, offset: header.len() })
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.offset..self.offset + N)?;
+        self.offset += N;
+        bytes.try_into().ok()
+    }
+
+    fn pubkey(&mut self) -> Option<Pubkey> {
+        self.take::<32>().map(Pubkey::new_from_array)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take::<8>().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        self.take::<8>().map(i64::from_le_bytes)
+    }
+}
@@ -12,14 +12,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod assertions;
+mod cli;
+mod compute;
 mod definition;
+mod encoding;
+mod fuzz;
 mod helpers;
+mod hints;
+mod idl;
+mod invariants;
+mod layout;
+mod model;
 mod mollusk;
+mod prerequisites;
+mod probe;
+mod rate_model;
+mod report;
+mod scenario;
+mod security;
 mod stages;
 
 use std::process::ExitCode;
 
 fn main() -> ExitCode {
+    // Arguments select local developer mode; the platform passes none
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args);
+    }
+
+    // Run the protocol fuzzer instead of the stages when requested
+    if let Ok(iterations) = std::env::var(fuzz::FUZZ_ENV) {
+        return fuzz::run(&iterations);
+    }
+
     // Collect all environment variables into a HashMap
     let env = std::env::vars().collect();
     // Build the definition from the definition module
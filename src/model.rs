@@ -0,0 +1,520 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference implementation of the challenge's lending math.
+//!
+//! The model applies deposits, withdrawals, borrows, repayments, interest
+//! accrual and liquidations to the same [`Bank`] and [`User`] layouts the
+//! program stores, so its results can be compared field by field with the
+//! program's accounts after an instruction.
+//!
+//! Shares are minted and burned pro rata to the bank's totals, rounding in
+//! the bank's favour. Amounts are in token base units; prices are in
+//! micro-USD per whole token.
+
+use crate::{
+    layout::{Bank, User},
+    rate_model::{BPS_DENOMINATOR, RateModel, SECONDS_PER_YEAR},
+};
+use std::fmt;
+
+/// Fields the model does not predict, such as timestamps.
+const UNMODELLED_FIELDS: &[&str] = &["last_updated", "health_factor", "interest_rate"];
+
+/// The two assets of the challenge's market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Asset {
+    Sol,
+    Usdc,
+}
+
+impl Asset {
+    /// Decimals of the asset's mint.
+    pub fn decimals(self) -> u32 {
+        match self {
+            Asset::Sol => 9,
+            Asset::Usdc => 6,
+        }
+    }
+
+    /// The asset borrows of this asset are collateralized by.
+    pub fn other(self) -> Asset {
+        match self {
+            Asset::Sol => Asset::Usdc,
+            Asset::Usdc => Asset::Sol,
+        }
+    }
+}
+
+/// Prices of both assets, in micro-USD per whole token.
+#[derive(Debug, Clone, Copy)]
+pub struct Prices {
+    pub sol: u64,
+    pub usdc: u64,
+}
+
+impl Prices {
+    /// Value of `amount` base units of `asset`, in micro-USD.
+    pub fn value(&self, asset: Asset, amount: u64) -> u128 {
+        let price = match asset {
+            Asset::Sol => self.sol,
+            Asset::Usdc => self.usdc,
+        };
+        u128::from(amount) * u128::from(price) / 10u128.pow(asset.decimals())
+    }
+
+    /// Base units of `asset` worth `value` micro-USD, rounded down.
+    pub fn amount(&self, asset: Asset, value: u128) -> u64 {
+        let price = match asset {
+            Asset::Sol => self.sol,
+            Asset::Usdc => self.usdc,
+        };
+        if price == 0 {
+            return 0;
+        }
+        let amount = value * 10u128.pow(asset.decimals()) / u128::from(price);
+        u64::try_from(amount).unwrap_or(u64::MAX)
+    }
+}
+
+/// Why the model rejects an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelError {
+    ZeroAmount,
+    InsufficientFunds,
+    OverBorrowableAmount,
+    OverRepay,
+    NotUndercollateralized,
+    MathOverflow,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::ZeroAmount => write!(f, "amount must be greater than zero"),
+            ModelError::InsufficientFunds => write!(f, "insufficient deposited funds"),
+            ModelError::OverBorrowableAmount => write!(f, "amount exceeds the borrowable amount"),
+            ModelError::OverRepay => write!(f, "amount exceeds the borrowed amount"),
+            ModelError::NotUndercollateralized => write!(f, "position is not undercollateralized"),
+            ModelError::MathOverflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+/// The result of a liquidation, in base units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct Liquidation {
+    /// Debt repaid by the liquidator.
+    pub repaid: u64,
+    /// Collateral transferred to the liquidator, including the bonus.
+    pub seized: u64,
+}
+
+/// Deposit `amount` of `asset`, minting deposit shares.
+pub fn deposit(
+    bank: &mut Bank,
+    user: &mut User,
+    asset: Asset,
+    amount: u64,
+) -> Result<(), ModelError> {
+    if amount == 0 {
+        return Err(ModelError::ZeroAmount);
+    }
+    let shares = shares_for(amount, bank.total_deposits, bank.total_deposit_shares, false)?;
+
+    bank.total_deposits = add(bank.total_deposits, amount)?;
+    bank.total_deposit_shares = add(bank.total_deposit_shares, shares)?;
+    let (deposited, deposited_shares) = deposits_mut(user, asset);
+    *deposited = add(*deposited, amount)?;
+    *deposited_shares = add(*deposited_shares, shares)?;
+    Ok(())
+}
+
+/// Withdraw `amount` of `asset`, burning deposit shares.
+pub fn withdraw(
+    bank: &mut Bank,
+    user: &mut User,
+    asset: Asset,
+    amount: u64,
+) -> Result<(), ModelError> {
+    if amount == 0 {
+        return Err(ModelError::ZeroAmount);
+    }
+    let (deposited, deposited_shares) = deposits_mut(user, asset);
+    if amount > *deposited {
+        return Err(ModelError::InsufficientFunds);
+    }
+    let shares = shares_for(amount, bank.total_deposits, bank.total_deposit_shares, true)?
+        .min(*deposited_shares);
+
+    *deposited -= amount;
+    *deposited_shares -= shares;
+    bank.total_deposits = sub(bank.total_deposits, amount)?;
+    bank.total_deposit_shares = sub(bank.total_deposit_shares, shares)?;
+    Ok(())
+}
+
+/// Borrow `amount` of `asset` against the user's deposits of the other
+/// asset, up to the bank's maximum loan-to-value.
+pub fn borrow(
+    bank: &mut Bank,
+    user: &mut User,
+    asset: Asset,
+    amount: u64,
+    prices: &Prices,
+) -> Result<(), ModelError> {
+    if amount == 0 {
+        return Err(ModelError::ZeroAmount);
+    }
+    let collateral = prices.value(asset.other(), deposits(user, asset.other()));
+    let borrowable = collateral * u128::from(bank.max_ltv) / u128::from(BPS_DENOMINATOR);
+    let debt = prices.value(asset, borrows(user, asset)) + prices.value(asset, amount);
+    if debt > borrowable {
+        return Err(ModelError::OverBorrowableAmount);
+    }
+    let shares = shares_for(amount, bank.total_borrowed, bank.total_borrowed_shares, true)?;
+
+    bank.total_borrowed = add(bank.total_borrowed, amount)?;
+    bank.total_borrowed_shares = add(bank.total_borrowed_shares, shares)?;
+    let (borrowed, borrowed_shares) = borrows_mut(user, asset);
+    *borrowed = add(*borrowed, amount)?;
+    *borrowed_shares = add(*borrowed_shares, shares)?;
+    Ok(())
+}
+
+/// Repay `amount` of the user's `asset` debt, burning borrow shares.
+pub fn repay(
+    bank: &mut Bank,
+    user: &mut User,
+    asset: Asset,
+    amount: u64,
+) -> Result<(), ModelError> {
+    if amount == 0 {
+        return Err(ModelError::ZeroAmount);
+    }
+    let (borrowed, borrowed_shares) = borrows_mut(user, asset);
+    if amount > *borrowed {
+        return Err(ModelError::OverRepay);
+    }
+    let shares = shares_for(amount, bank.total_borrowed, bank.total_borrowed_shares, false)?
+        .min(*borrowed_shares);
+
+    *borrowed -= amount;
+    *borrowed_shares -= shares;
+    bank.total_borrowed = sub(bank.total_borrowed, amount)?;
+    bank.total_borrowed_shares = sub(bank.total_borrowed_shares, shares)?;
+    Ok(())
+}
+
+/// Accrue simple interest on the bank's borrows up to `now`, crediting it to
+/// depositors.
+pub fn accrue_interest(bank: &mut Bank, model: &RateModel, now: i64) -> Result<(), ModelError> {
+    let elapsed = now.saturating_sub(bank.last_updated).max(0) as u128;
+    let utilization = utilization_bps(bank);
+    let rate = model.borrow_rate_bps(utilization);
+    let interest = u128::from(bank.total_borrowed) * u128::from(rate) * elapsed /
+        (u128::from(BPS_DENOMINATOR) * SECONDS_PER_YEAR as u128);
+    let interest = u64::try_from(interest).map_err(|_| ModelError::MathOverflow)?;
+
+    bank.total_borrowed = add(bank.total_borrowed, interest)?;
+    bank.total_deposits = add(bank.total_deposits, interest)?;
+    bank.last_updated = now;
+    Ok(())
+}
+
+/// The bank's utilization, in basis points.
+pub fn utilization_bps(bank: &Bank) -> u64 {
+    if bank.total_deposits == 0 {
+        return 0;
+    }
+    let utilization = u128::from(bank.total_borrowed) * u128::from(BPS_DENOMINATOR) /
+        u128::from(bank.total_deposits);
+    u64::try_from(utilization).unwrap_or(u64::MAX)
+}
+
+/// The user's health factor in basis points: liquidation-weighted collateral
+/// over debt, so anything below 10,000 can be liquidated. A user without
+/// debt has a health factor of `u64::MAX`.
+pub fn health_factor_bps(user: &User, prices: &Prices, liquidation_threshold: u64) -> u64 {
+    let debt =
+        prices.value(Asset::Sol, user.borrowed_sol) + prices.value(Asset::Usdc, user.borrowed_usdc);
+    if debt == 0 {
+        return u64::MAX;
+    }
+    let collateral = prices.value(Asset::Sol, user.deposited_sol) +
+        prices.value(Asset::Usdc, user.deposited_usdc);
+    let health = collateral * u128::from(liquidation_threshold) / debt;
+    u64::try_from(health).unwrap_or(u64::MAX)
+}
+
+/// Liquidate an undercollateralized user.
+///
+/// The liquidator repays the close factor's share of the user's `borrowed`
+/// debt and receives collateral worth the repaid value plus the liquidation
+/// bonus, capped at the user's collateral.
+pub fn liquidate(
+    collateral_bank: &mut Bank,
+    borrowed_bank: &mut Bank,
+    user: &mut User,
+    borrowed: Asset,
+    prices: &Prices,
+) -> Result<Liquidation, ModelError> {
+    let collateral = borrowed.other();
+    if health_factor_bps(user, prices, collateral_bank.liquidation_threshold) >= BPS_DENOMINATOR {
+        return Err(ModelError::NotUndercollateralized);
+    }
+
+    let debt = borrows(user, borrowed);
+    let repaid = u64::try_from(
+        u128::from(debt) * u128::from(borrowed_bank.liquidation_close_factor) /
+            u128::from(BPS_DENOMINATOR),
+    )
+    .map_err(|_| ModelError::MathOverflow)?;
+    let seized_value = prices.value(borrowed, repaid) *
+        u128::from(BPS_DENOMINATOR + collateral_bank.liquidation_bonus) /
+        u128::from(BPS_DENOMINATOR);
+    let seized = prices.amount(collateral, seized_value).min(deposits(user, collateral));
+
+    repay(borrowed_bank, user, borrowed, repaid)?;
+    if seized > 0 {
+        withdraw(collateral_bank, user, collateral, seized)?;
+    }
+    Ok(Liquidation { repaid, seized })
+}
+
+/// Compare a state predicted by the model with the program's.
+///
+/// # Arguments
+///
+/// * `expected` - The model's fields, e.g. from [`Bank::fields`]
+/// * `actual` - The program's fields for the same account
+///
+/// # Returns
+///
+/// * `Vec<String>` - One line per modelled field that differs
+pub fn mismatches(
+    expected: &[(&'static str, String)],
+    actual: &[(&'static str, String)],
+) -> Vec<String> {
+    expected
+        .iter()
+        .zip(actual)
+        .filter(|((name, expected), (_, actual))| {
+            !UNMODELLED_FIELDS.contains(name) && expected != actual
+        })
+        .map(|((name, expected), (_, actual))| {
+            format!("{}: model {}, program {}", name, expected, actual)
+        })
+        .collect()
+}
+
+/// Shares worth `amount` against a pool's totals. An empty pool mints one
+/// share per unit.
+fn shares_for(
+    amount: u64,
+    total: u64,
+    total_shares: u64,
+    round_up: bool,
+) -> Result<u64, ModelError> {
+    if total == 0 || total_shares == 0 {
+        return Ok(amount);
+    }
+    let numerator = u128::from(amount) * u128::from(total_shares);
+    let total = u128::from(total);
+    let shares = if round_up { numerator.div_ceil(total) } else { numerator / total };
+    u64::try_from(shares).map_err(|_| ModelError::MathOverflow)
+}
+
+fn deposits(user: &User, asset: Asset) -> u64 {
+    match asset {
+        Asset::Sol => user.deposited_sol,
+        Asset::Usdc => user.deposited_usdc,
+    }
+}
+
+fn borrows(user: &User, asset: Asset) -> u64 {
+    match asset {
+        Asset::Sol => user.borrowed_sol,
+        Asset::Usdc => user.borrowed_usdc,
+    }
+}
+
+fn deposits_mut(user: &mut User, asset: Asset) -> (&mut u64, &mut u64) {
+    match asset {
+        Asset::Sol => (&mut user.deposited_sol, &mut user.deposited_sol_shares),
+        Asset::Usdc => (&mut user.deposited_usdc, &mut user.deposited_usdc_shares),
+    }
+}
+
+fn borrows_mut(user: &mut User, asset: Asset) -> (&mut u64, &mut u64) {
+    match asset {
+        Asset::Sol => (&mut user.borrowed_sol, &mut user.borrowed_sol_shares),
+        Asset::Usdc => (&mut user.borrowed_usdc, &mut user.borrowed_usdc_shares),
+    }
+}
+
+fn add(a: u64, b: u64) -> Result<u64, ModelError> {
+    a.checked_add(b).ok_or(ModelError::MathOverflow)
+}
+
+fn sub(a: u64, b: u64) -> Result<u64, ModelError> {
+    a.checked_sub(b).ok_or(ModelError::MathOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// $100 per SOL and $1 per USDC, in micro-USD.
+    const PRICES: Prices = Prices { sol: 100_000_000, usdc: 1_000_000 };
+
+    #[test]
+    fn deposit_rounds_shares_down() {
+        let mut bank = Bank { total_deposits: 3, total_deposit_shares: 2, ..Default::default() };
+        let mut user = User::default();
+        deposit(&mut bank, &mut user, Asset::Usdc, 2).unwrap();
+        assert_eq!(user.deposited_usdc, 2);
+        assert_eq!(user.deposited_usdc_shares, 1);
+        assert_eq!((bank.total_deposits, bank.total_deposit_shares), (5, 3));
+    }
+
+    #[test]
+    fn withdraw_rounds_shares_up() {
+        let mut bank = Bank { total_deposits: 3, total_deposit_shares: 2, ..Default::default() };
+        let mut user = User { deposited_usdc: 3, deposited_usdc_shares: 2, ..Default::default() };
+        withdraw(&mut bank, &mut user, Asset::Usdc, 1).unwrap();
+        assert_eq!((user.deposited_usdc, user.deposited_usdc_shares), (2, 1));
+        assert_eq!((bank.total_deposits, bank.total_deposit_shares), (2, 1));
+        assert_eq!(
+            withdraw(&mut bank, &mut user, Asset::Usdc, 3),
+            Err(ModelError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn borrow_rounds_shares_up_and_respects_max_ltv() {
+        let mut bank = Bank {
+            total_borrowed: 3,
+            total_borrowed_shares: 2,
+            max_ltv: 7_500,
+            ..Default::default()
+        };
+        let mut user = User { deposited_sol: 1_000_000_000, ..Default::default() };
+        borrow(&mut bank, &mut user, Asset::Usdc, 2, &PRICES).unwrap();
+        assert_eq!((user.borrowed_usdc, user.borrowed_usdc_shares), (2, 2));
+        assert_eq!((bank.total_borrowed, bank.total_borrowed_shares), (5, 4));
+
+        // One SOL at $100 supports $75 of debt, two base units of which are
+        // already borrowed.
+        assert_eq!(
+            borrow(&mut bank, &mut user, Asset::Usdc, 75_000_000, &PRICES),
+            Err(ModelError::OverBorrowableAmount)
+        );
+        borrow(&mut bank, &mut user, Asset::Usdc, 74_999_998, &PRICES).unwrap();
+    }
+
+    #[test]
+    fn repay_rounds_shares_down() {
+        let mut bank = Bank { total_borrowed: 3, total_borrowed_shares: 2, ..Default::default() };
+        let mut user = User { borrowed_usdc: 3, borrowed_usdc_shares: 2, ..Default::default() };
+        repay(&mut bank, &mut user, Asset::Usdc, 1).unwrap();
+        assert_eq!((user.borrowed_usdc, user.borrowed_usdc_shares), (2, 2));
+        assert_eq!((bank.total_borrowed, bank.total_borrowed_shares), (2, 2));
+        assert_eq!(repay(&mut bank, &mut user, Asset::Usdc, 3), Err(ModelError::OverRepay));
+    }
+
+    #[test]
+    fn interest_accrues_at_the_kinked_rate() {
+        let mut bank = Bank {
+            total_deposits: 10_000_000,
+            total_borrowed: 8_000_000,
+            last_updated: 1_700_000_000,
+            ..Default::default()
+        };
+        let model = crate::rate_model::CHALLENGE_RATE_MODEL;
+        accrue_interest(&mut bank, &model, 1_700_000_000 + SECONDS_PER_YEAR).unwrap();
+        // 80% utilization is the kink: 2% base plus the 4% first slope.
+        assert_eq!(bank.total_borrowed, 8_480_000);
+        assert_eq!(bank.total_deposits, 10_480_000);
+        assert_eq!(bank.last_updated, 1_700_000_000 + SECONDS_PER_YEAR);
+    }
+
+    /// A SOL collateral bank, a USDC debt bank and a user with 1 SOL of
+    /// collateral and 90 USDC of debt.
+    fn liquidation_market() -> (Bank, Bank, User) {
+        let collateral_bank = Bank {
+            total_deposits: 1_000_000_000,
+            total_deposit_shares: 1_000_000_000,
+            liquidation_threshold: 8_000,
+            liquidation_bonus: 500,
+            ..Default::default()
+        };
+        let borrowed_bank = Bank {
+            total_borrowed: 90_000_000,
+            total_borrowed_shares: 90_000_000,
+            liquidation_close_factor: 5_000,
+            ..Default::default()
+        };
+        let user = User {
+            deposited_sol: 1_000_000_000,
+            deposited_sol_shares: 1_000_000_000,
+            borrowed_usdc: 90_000_000,
+            borrowed_usdc_shares: 90_000_000,
+            ..Default::default()
+        };
+        (collateral_bank, borrowed_bank, user)
+    }
+
+    #[test]
+    fn liquidation_repays_close_factor_and_seizes_bonus() {
+        let (mut collateral_bank, mut borrowed_bank, mut user) = liquidation_market();
+        assert_eq!(health_factor_bps(&user, &PRICES, 8_000), 8_888);
+
+        let liquidation =
+            liquidate(&mut collateral_bank, &mut borrowed_bank, &mut user, Asset::Usdc, &PRICES)
+                .unwrap();
+        // Half of the $90 debt, and $45 plus a 5% bonus of SOL at $100.
+        assert_eq!(liquidation, Liquidation { repaid: 45_000_000, seized: 472_500_000 });
+        assert_eq!(user.borrowed_usdc, 45_000_000);
+        assert_eq!(user.deposited_sol, 527_500_000);
+        assert_eq!(borrowed_bank.total_borrowed, 45_000_000);
+        assert_eq!(collateral_bank.total_deposits, 527_500_000);
+    }
+
+    #[test]
+    fn liquidation_seizes_at_most_the_collateral() {
+        let (mut collateral_bank, mut borrowed_bank, mut user) = liquidation_market();
+        let prices = Prices { sol: 40_000_000, ..PRICES };
+
+        let liquidation =
+            liquidate(&mut collateral_bank, &mut borrowed_bank, &mut user, Asset::Usdc, &prices)
+                .unwrap();
+        assert_eq!(liquidation, Liquidation { repaid: 45_000_000, seized: 1_000_000_000 });
+        assert_eq!((user.deposited_sol, user.deposited_sol_shares), (0, 0));
+    }
+
+    #[test]
+    fn healthy_position_cannot_be_liquidated() {
+        let (mut collateral_bank, mut borrowed_bank, mut user) = liquidation_market();
+        let prices = Prices { sol: 200_000_000, ..PRICES };
+        let before = (collateral_bank.clone(), borrowed_bank.clone(), user.clone());
+
+        assert_eq!(
+            liquidate(&mut collateral_bank, &mut borrowed_bank, &mut user, Asset::Usdc, &prices),
+            Err(ModelError::NotUndercollateralized)
+        );
+        assert_eq!((collateral_bank, borrowed_bank, user), before);
+    }
+}
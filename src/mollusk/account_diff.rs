@@ -0,0 +1,222 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Human-readable diffs of the accounts an instruction touched.
+//!
+//! Every changed account gets a before/after table of its lamports, owner,
+//! data length and executable flag. SPL Token accounts and mints and the
+//! challenge's `Bank` and `User` accounts are decoded, so their changes are
+//! shown field by field instead of as raw bytes.
+
+use crate::layout::{Bank, User};
+use solana_account::Account;
+use solana_program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token_interface::state::{Account as TokenAccount, Mint};
+use std::{collections::HashMap, fmt};
+
+/// The changes an instruction made to the accounts it touched.
+#[derive(Debug, Default)]
+pub struct AccountDiff {
+    changes: Vec<AccountChange>,
+}
+
+/// The changed fields of a single account.
+#[derive(Debug)]
+struct AccountChange {
+    pubkey: Pubkey,
+    kind: &'static str,
+    fields: Vec<FieldChange>,
+}
+
+/// A single field's value before and after the instruction.
+#[derive(Debug)]
+struct FieldChange {
+    name: String,
+    before: String,
+    after: String,
+}
+
+impl fmt::Display for AccountDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "no account changed");
+        }
+
+        for (index, change) in self.changes.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{} ({}):", change.pubkey, change.kind)?;
+
+            let name_width =
+                change.fields.iter().map(|field| field.name.len()).max().unwrap_or(0).max(5);
+            let before_width =
+                change.fields.iter().map(|field| field.before.len()).max().unwrap_or(0).max(6);
+            write!(f, "  {:<name_width$}  {:<before_width$}  after", "field", "before")?;
+            for field in &change.fields {
+                write!(
+                    f,
+                    "\n  {:<name_width$}  {:<before_width$}  {}",
+                    field.name, field.before, field.after
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Diff the accounts an instruction returned against their prior state.
+///
+/// Accounts missing from `before` are treated as newly created, and
+/// accounts whose lamports and data are both empty afterwards as closed.
+///
+/// # Arguments
+///
+/// * `before` - The account state before the instruction
+/// * `after` - The resulting accounts returned by Mollusk
+///
+/// # Returns
+///
+/// * `AccountDiff` - The changed accounts, in the order they were returned
+pub fn diff_accounts(
+    before: &HashMap<Pubkey, Account>,
+    after: &[(Pubkey, Account)],
+) -> AccountDiff {
+    let changes = after
+        .iter()
+        .filter_map(|(pubkey, after)| {
+            let before = before.get(pubkey);
+            if before == Some(after) {
+                return None;
+            }
+            Some(diff_account(*pubkey, before, after))
+        })
+        .collect();
+    AccountDiff { changes }
+}
+
+fn diff_account(pubkey: Pubkey, before: Option<&Account>, after: &Account) -> AccountChange {
+    let closed = after.lamports == 0 && after.data.is_empty();
+    let before_decoded = before.and_then(decode);
+    let after_decoded = decode(after);
+    let kind = if closed {
+        "closed"
+    } else {
+        match (&before_decoded, &after_decoded) {
+            (_, Some((kind, _))) | (Some((kind, _)), None) => *kind,
+            (None, None) if before.is_none() => "created",
+            (None, None) => "account",
+        }
+    };
+
+    let absent = || "-".to_string();
+    let mut fields = Vec::new();
+    let mut push = |name: &str, before: String, after: String| {
+        if before != after {
+            fields.push(FieldChange { name: name.to_string(), before, after });
+        }
+    };
+
+    push(
+        "lamports",
+        before.map_or_else(absent, |account| account.lamports.to_string()),
+        after.lamports.to_string(),
+    );
+    push(
+        "owner",
+        before.map_or_else(absent, |account| account.owner.to_string()),
+        after.owner.to_string(),
+    );
+    push(
+        "data length",
+        before.map_or_else(absent, |account| account.data.len().to_string()),
+        after.data.len().to_string(),
+    );
+    push(
+        "executable",
+        before.map_or_else(absent, |account| account.executable.to_string()),
+        after.executable.to_string(),
+    );
+
+    match (before_decoded, after_decoded) {
+        (Some((before_kind, before_fields)), Some((after_kind, after_fields)))
+            if before_kind == after_kind =>
+        {
+            for ((name, before), (_, after)) in before_fields.into_iter().zip(after_fields) {
+                push(name, before, after);
+            }
+        }
+        (None, None) => {
+            if let Some(before) = before &&
+                before.data.len() == after.data.len() &&
+                before.data != after.data
+            {
+                let changed = before.data.iter().zip(&after.data).filter(|(a, b)| a != b).count();
+                push("data", "-".to_string(), format!("{} bytes changed", changed));
+            }
+        }
+        (before_decoded, after_decoded) => {
+            // The account changed type, or only one side decodes; list
+            // whichever fields are known on each side.
+            for (name, before) in before_decoded.into_iter().flat_map(|(_, fields)| fields) {
+                push(name, before, absent());
+            }
+            for (name, after) in after_decoded.into_iter().flat_map(|(_, fields)| fields) {
+                push(name, absent(), after);
+            }
+        }
+    }
+
+    AccountChange { pubkey, kind, fields }
+}
+
+/// Decode an account as one of the recognized types.
+fn decode(account: &Account) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+    if account.owner == spl_token_interface::ID {
+        if account.data.len() == TokenAccount::LEN {
+            let token = TokenAccount::unpack(&account.data).ok()?;
+            return Some((
+                "token account",
+                vec![
+                    ("mint", token.mint.to_string()),
+                    ("token owner", token.owner.to_string()),
+                    ("amount", token.amount.to_string()),
+                    ("delegate", format!("{:?}", token.delegate)),
+                    ("delegated_amount", token.delegated_amount.to_string()),
+                    ("state", format!("{:?}", token.state)),
+                    ("close_authority", format!("{:?}", token.close_authority)),
+                ],
+            ));
+        }
+        if account.data.len() == Mint::LEN {
+            let mint = Mint::unpack(&account.data).ok()?;
+            return Some((
+                "mint",
+                vec![
+                    ("mint_authority", format!("{:?}", mint.mint_authority)),
+                    ("supply", mint.supply.to_string()),
+                    ("decimals", mint.decimals.to_string()),
+                    ("freeze_authority", format!("{:?}", mint.freeze_authority)),
+                ],
+            ));
+        }
+        return None;
+    }
+
+    if let Some(bank) = Bank::decode(&account.data) {
+        return Some(("Bank", bank.fields()));
+    }
+    User::decode(&account.data).map(|user| ("User", user.fields()))
+}
@@ -0,0 +1,177 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-process attacker program for the reentrancy stage.
+//!
+//! The attacker is a builtin registered with Mollusk under
+//! [`ATTACKER_PROGRAM_ID`]. Passed to the lending program in place of the SPL
+//! Token program, it records that it was invoked, tries to call back into
+//! the lending program with a `withdraw`, and then reports success whatever
+//! the outcome of the callback, as a malicious token program would.
+//!
+//! What the attacker observes is kept by the [`LendingTestContext`] that
+//! registered it. The context installs its state with [`run_with`] only
+//! while one of its own instructions executes, so concurrent tests and
+//! other contexts never see or overwrite it.
+//!
+//! [`LendingTestContext`]: crate::mollusk::LendingTestContext
+
+use crate::encoding::instruction_data;
+use mollusk_svm::{Mollusk, program::keyed_account_for_system_program};
+use solana_account::Account;
+use solana_instruction::{AccountMeta, Instruction};
+use solana_program_runtime::{declare_process_instruction, loaded_programs::ProgramCacheEntry};
+use solana_pubkey::Pubkey;
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+/// Program ID the attacker is registered under.
+pub const ATTACKER_PROGRAM_ID: Pubkey = Pubkey::new_from_array([0xa7; 32]);
+
+/// Name of the attacker builtin.
+const ATTACKER_NAME: &str = "lending_tester_attacker";
+
+/// Compute units charged for a single attacker invocation.
+const ATTACKER_COMPUTE_UNITS: u64 = 1_000;
+
+/// What the attacker observed during the last instruction.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttackerOutcome {
+    /// The lending program invoked the attacker.
+    pub invoked: bool,
+    /// The attacker's callback into the lending program succeeded.
+    pub reentered: bool,
+}
+
+/// The program the attacker calls back into, and what it observed.
+#[derive(Debug)]
+pub struct AttackerState {
+    target: Pubkey,
+    outcome: AttackerOutcome,
+}
+
+impl AttackerState {
+    /// State for an attacker that calls back into `target`.
+    pub fn new(target: Pubkey) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self { target, outcome: AttackerOutcome::default() }))
+    }
+
+    /// What the attacker observed during the last instruction.
+    pub fn outcome(&self) -> AttackerOutcome {
+        self.outcome
+    }
+}
+
+thread_local! {
+    /// The state of the context whose instruction is executing, if that
+    /// context registered the attacker.
+    static ACTIVE: RefCell<Option<Rc<RefCell<AttackerState>>>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with `state` as the attacker's state, clearing its previous
+/// outcome first.
+///
+/// # Arguments
+///
+/// * `state` - The executing context's attacker state, if any
+/// * `f` - Executes the context's instruction
+///
+/// # Returns
+///
+/// * `T` - The result of `f`
+pub fn run_with<T>(state: Option<&Rc<RefCell<AttackerState>>>, f: impl FnOnce() -> T) -> T {
+    if let Some(state) = state {
+        state.borrow_mut().outcome = AttackerOutcome::default();
+    }
+    let _restore = Restore(ACTIVE.replace(state.cloned()));
+    f()
+}
+
+/// Reinstates the previously active state when dropped, so a panicking
+/// instruction does not leave its context's state installed.
+struct Restore(Option<Rc<RefCell<AttackerState>>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ACTIVE.set(self.0.take());
+    }
+}
+
+declare_process_instruction!(Entrypoint, ATTACKER_COMPUTE_UNITS, |invoke_context| {
+    let Some(state) = ACTIVE.with_borrow(Option::clone) else {
+        return Ok(());
+    };
+    let target = {
+        let mut state = state.borrow_mut();
+        state.outcome.invoked = true;
+        state.target
+    };
+
+    // Forward every account the lending program passed us to the callback.
+    let transaction_context = &invoke_context.transaction_context;
+    let instruction_context = transaction_context.get_current_instruction_context()?;
+    let mut accounts = Vec::new();
+    for index in 0..instruction_context.get_number_of_instruction_accounts() {
+        let index_in_transaction =
+            instruction_context.get_index_of_instruction_account_in_transaction(index)?;
+        accounts.push(AccountMeta {
+            pubkey: *transaction_context.get_key_of_account_at_index(index_in_transaction)?,
+            is_signer: instruction_context.is_instruction_account_signer(index)?,
+            is_writable: instruction_context.is_instruction_account_writable(index)?,
+        });
+    }
+
+    // Without a `withdraw` tag there is nothing to re-enter with.
+    let Ok(data) = instruction_data("withdraw", &u64::MAX.to_le_bytes()) else {
+        return Ok(());
+    };
+    let callback = Instruction::new_with_bytes(target, &data, accounts);
+
+    // A malicious token program swallows the callback's failure.
+    let reentered = invoke_context.native_invoke(callback.into(), &[]).is_ok();
+    state.borrow_mut().outcome.reentered = reentered;
+    Ok(())
+});
+
+/// Register the attacker builtin with a Mollusk instance.
+pub fn add_attacker_program(mollusk: &mut Mollusk) {
+    mollusk.program_cache.cache().replenish(
+        ATTACKER_PROGRAM_ID,
+        Arc::new(ProgramCacheEntry::new_builtin(0, ATTACKER_NAME.len(), Entrypoint::vm)),
+    );
+}
+
+/// The attacker's program account, owned by the native loader like any
+/// other builtin.
+pub fn attacker_program_account() -> Account {
+    let (_, mut account) = keyed_account_for_system_program();
+    account.data = ATTACKER_NAME.as_bytes().to_vec();
+    account
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    #[test]
+    fn run_with_restores_the_previous_state_after_a_panic() {
+        let state = AttackerState::new(Pubkey::new_unique());
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            run_with::<()>(Some(&state), || panic!("instruction panicked"))
+        }));
+
+        assert!(result.is_err());
+        assert!(ACTIVE.with_borrow(Option::is_none));
+    }
+}
@@ -18,15 +18,24 @@
 //! testing of the lending program. It handles program loading, account setup,
 //! and instruction execution.
 
+pub mod account_diff;
+pub mod attacker;
 pub mod program_loader;
 pub mod test_context;
 
-pub use program_loader::{ProgramLoadError, load_lending_program, load_lending_program_id};
+pub use program_loader::{
+    ElfInfo, ProgramIdSource, ProgramLoadError, find_deploy_keypair, find_lending_program_id,
+    inspect_program_elf, load_lending_program, load_lending_program_id, load_program_elf,
+};
 pub use test_context::{LendingTestContext, TestContextError};
 
-use mollusk_svm::Mollusk;
+use mollusk_svm::{Mollusk, program::loader_keys};
 use solana_pubkey::Pubkey;
-use std::path::Path;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
 /// The default program ID for the lending program.
 /// This should match the program ID defined in the user's Anchor.toml.
@@ -36,11 +45,25 @@ pub const LENDING_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
     0x2b, 0x8c, 0x1d, 0x4e, 0x5f, 0x6a, 0x7b, 0x8c, 0x9d, 0x0e, 0x1f, 0x2a, 0x3b, 0x4c, 0x5d, 0x6e,
 ]);
 
-/// Create a new Mollusk instance for testing the lending program.
+/// The user's program as loaded from disk, shared by every stage.
+#[derive(Debug)]
+pub struct LoadedProgram {
+    /// The program ID from Anchor.toml.
+    pub program_id: Pubkey,
+    /// The SO file the ELF was read from.
+    pub path: PathBuf,
+    /// The program ELF bytes.
+    pub elf: Vec<u8>,
+}
+
+/// The program loaded for a repository directory, if any.
+static LOADED_PROGRAM: Mutex<Option<(PathBuf, Arc<LoadedProgram>)>> = Mutex::new(None);
+
+/// Load the user's program once per process.
 ///
-/// This function attempts to load the compiled lending program from the
-/// user's repository directory and creates a Mollusk instance configured
-/// for testing.
+/// Anchor.toml is parsed and the SO file located and read on the first call;
+/// later calls for the same repository return the cached program. Failures
+/// are not cached, so every stage reports its own load error.
 ///
 /// # Arguments
 ///
@@ -48,31 +71,89 @@ pub const LENDING_PROGRAM_ID: Pubkey = Pubkey::new_from_array([
 ///
 /// # Returns
 ///
-/// * `Ok(Mollusk)` - A configured Mollusk instance
+/// * `Ok(Arc<LoadedProgram>)` - The shared program
 /// * `Err(ProgramLoadError)` - If the program cannot be loaded
-pub fn create_lending_mollusk(
-    repo_dir: &Path,
-    program_id: &Pubkey,
-) -> Result<Mollusk, ProgramLoadError> {
-    let program_path = load_lending_program(repo_dir)?;
-    let program_name =
-        program_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("lending_program");
-
-    let program_dir = program_path
-        .parent()
-        .ok_or_else(|| ProgramLoadError::ProgramDirNotFound(program_path.clone()))?;
-
-    // SAFETY: set_var is process-global; we set it once before loading the ELF.
-    unsafe {
-        std::env::set_var("SBF_OUT_DIR", program_dir);
+pub fn load_shared_program(repo_dir: &Path) -> Result<Arc<LoadedProgram>, ProgramLoadError> {
+    let mut cached = LOADED_PROGRAM.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some((dir, program)) = cached.as_ref() &&
+        dir == repo_dir
+    {
+        return Ok(Arc::clone(program));
     }
 
-    let mut mollusk = Mollusk::new(program_id, program_name);
+    let source = find_lending_program_id(repo_dir)?;
+    let path = load_lending_program(repo_dir)?;
+    let elf = load_program_elf(&path)?;
+    crate::report::log(format!("Using program ID {}", source));
+    let program = Arc::new(LoadedProgram { program_id: source.program_id, path, elf });
+
+    *cached = Some((repo_dir.to_path_buf(), Arc::clone(&program)));
+    Ok(program)
+}
+
+/// Create a Mollusk instance with a loaded program registered under its
+/// program ID.
+///
+/// # Arguments
+///
+/// * `program` - The loaded program
+///
+/// # Returns
+///
+/// * `Mollusk` - A configured Mollusk instance
+pub fn create_program_mollusk(program: &LoadedProgram) -> Mollusk {
+    let mut mollusk = Mollusk::default();
+    mollusk.add_program_with_elf_and_loader(
+        &program.program_id,
+        &program.elf,
+        &loader_keys::LOADER_V3,
+    );
 
     // Add necessary programs for testing
     add_required_programs(&mut mollusk);
 
-    Ok(mollusk)
+    mollusk
+}
+
+/// Check the user's program before running any stage.
+///
+/// The SO file is located, its ELF header is checked to be a BPF/SBF shared
+/// object, and it is loaded into a Mollusk program cache so that a corrupt
+/// or unsupported program is reported here rather than as a panic later.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(ElfInfo)` - The program's path, size and SBF version
+/// * `Err(ProgramLoadError)` - If the program cannot be found or loaded
+pub fn preflight_program(repo_dir: &Path) -> Result<ElfInfo, ProgramLoadError> {
+    let program = load_shared_program(repo_dir)?;
+    let info = inspect_program_elf(&program.path, &program.elf)?;
+
+    // Mollusk panics on ELFs the program runtime rejects; silence the panic
+    // hook so the failure is reported once, as an error.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let loaded = panic::catch_unwind(AssertUnwindSafe(|| {
+        create_program_mollusk(&program);
+    }));
+    panic::set_hook(hook);
+
+    loaded.map(|_| info).map_err(|payload| {
+        let reason = payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .or_else(|| payload.downcast_ref::<&str>().copied())
+            .unwrap_or("unknown error");
+        ProgramLoadError::ElfLoadError(format!(
+            "{}: the program runtime rejected it: {}",
+            program.path.display(),
+            reason
+        ))
+    })
 }
 
 /// Add required programs to the Mollusk instance.
@@ -90,7 +171,9 @@ fn add_required_programs(mollusk: &mut Mollusk) {
 /// Initialize a test context with the lending program.
 ///
 /// This is a convenience function that creates both a Mollusk instance and
-/// a test context for easier testing.
+/// a test context for easier testing. The program is loaded from disk only
+/// once per process; every context gets a fresh Mollusk instance and account
+/// state.
 ///
 /// # Arguments
 ///
@@ -101,7 +184,9 @@ fn add_required_programs(mollusk: &mut Mollusk) {
 /// * `Ok(LendingTestContext)` - A configured test context
 /// * `Err(TestContextError)` - If initialization fails
 pub fn init_test_context(repo_dir: &Path) -> Result<LendingTestContext, TestContextError> {
-    let program_id = load_lending_program_id(repo_dir)?;
-    let mollusk = create_lending_mollusk(repo_dir, &program_id)?;
-    LendingTestContext::new(mollusk, program_id)
+    // Fail on an invalid stackclass.yml here, before any instruction is
+    // encoded with it.
+    crate::encoding::load(repo_dir)?;
+    let program = load_shared_program(repo_dir)?;
+    LendingTestContext::new(create_program_mollusk(&program), program.program_id)
 }
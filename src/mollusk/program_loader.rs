@@ -14,9 +14,9 @@
 
 //! Program loader module for loading the lending program from disk.
 
-use mollusk_svm::file;
 use solana_pubkey::Pubkey;
 use std::{
+    io::Read,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -28,11 +28,15 @@ pub enum ProgramLoadError {
     AnchorTomlNotFound(PathBuf),
     ProgramIdNotFound,
     InvalidProgramId(String),
-    ProgramDirNotFound(PathBuf),
-    ProgramNotFound,
+    InvalidAnchorToml(String),
+    AmbiguousProgramId(Vec<String>),
+    ProgramNotFound(Vec<(PathBuf, String)>),
     IoError(std::io::Error),
-    #[allow(dead_code)]
     ElfLoadError(String),
+    IdlNotFound(PathBuf),
+    InvalidIdl(String),
+    InvalidKeypair(String),
+    InvalidConfig(String),
 }
 
 impl std::fmt::Display for ProgramLoadError {
@@ -50,14 +54,35 @@ impl std::fmt::Display for ProgramLoadError {
             ProgramLoadError::InvalidProgramId(value) => {
                 write!(f, "Invalid program ID in Anchor.toml: {}", value)
             }
-            ProgramLoadError::ProgramDirNotFound(path) => {
-                write!(f, "Program directory not found: {}", path.display())
+            ProgramLoadError::InvalidAnchorToml(msg) => {
+                write!(f, "Failed to parse Anchor.toml: {}", msg)
             }
-            ProgramLoadError::ProgramNotFound => {
-                write!(f, "Program SO file not found in any of the expected locations")
+            ProgramLoadError::AmbiguousProgramId(candidates) => write!(
+                f,
+                "Multiple programs in Anchor.toml, set {} to one of: {}",
+                PROGRAM_NAME_ENV,
+                candidates.join(", ")
+            ),
+            ProgramLoadError::ProgramNotFound(searched) => {
+                write!(f, "Program SO file not found in any of the expected locations")?;
+                if !searched.is_empty() {
+                    write!(f, "; looked at:")?;
+                    for (path, reason) in searched {
+                        write!(f, "\n  {} ({})", path.display(), reason)?;
+                    }
+                }
+                Ok(())
             }
             ProgramLoadError::IoError(err) => write!(f, "Failed to read program file: {}", err),
             ProgramLoadError::ElfLoadError(msg) => write!(f, "Failed to load program ELF: {}", msg),
+            ProgramLoadError::IdlNotFound(path) => {
+                write!(f, "Program IDL not found (run `anchor build`): {}", path.display())
+            }
+            ProgramLoadError::InvalidIdl(msg) => write!(f, "Failed to parse program IDL: {}", msg),
+            ProgramLoadError::InvalidKeypair(msg) => {
+                write!(f, "Failed to read program deploy keypair: {}", msg)
+            }
+            ProgramLoadError::InvalidConfig(msg) => write!(f, "Invalid stackclass.yml: {}", msg),
         }
     }
 }
@@ -77,14 +102,32 @@ impl From<std::io::Error> for ProgramLoadError {
     }
 }
 
+/// Directories searched for the program SO file, relative to the repository.
+const SEARCH_DIRS: [&str; 3] = ["target/deploy", "target/sbf-solana-solana/release", "artifacts"];
+
+/// ELF machine type of eBPF programs.
+const EM_BPF: u16 = 247;
+
+/// ELF machine type of SBF programs.
+const EM_SBF: u16 = 263;
+
+/// ELF type of shared objects, which on-chain programs are.
+const ET_DYN: u16 = 3;
+
 /// Load the lending program from the user's repository directory.
 ///
-/// This function searches for the compiled program SO file in the following
-/// locations (in order):
+/// The SO file must be named after the program: the program key in
+/// Anchor.toml, the library name of the matching crate under `programs/`, or
+/// `lending_program`. Matching files are looked for in the following
+/// locations, then anywhere under `target/`:
+///
+/// 1. `repo_dir/target/deploy`
+/// 2. `repo_dir/target/sbf-solana-solana/release`
+/// 3. `repo_dir/artifacts`
 ///
-/// 1. `repo_dir/target/deploy/lending_program.so`
-/// 2. `repo_dir/target/sbf-solana-solana/release/lending_program.so`
-/// 3. `repo_dir/artifacts/lending_program.so`
+/// Files that are not SBF/BPF ELF objects are skipped, and of the remaining
+/// candidates the most recently modified one wins. If none remains, the error
+/// lists why each candidate was rejected.
 ///
 /// # Arguments
 ///
@@ -93,42 +136,180 @@ impl From<std::io::Error> for ProgramLoadError {
 /// # Returns
 ///
 /// * `Ok(PathBuf)` - Path to the program SO file
-/// * `Err(ProgramLoadError)` - If the program cannot be found or loaded
+/// * `Err(ProgramLoadError)` - If no candidate is usable, listing every path that was looked at
 pub fn load_lending_program(repo_dir: &Path) -> Result<PathBuf, ProgramLoadError> {
     if !repo_dir.exists() {
         return Err(ProgramLoadError::RepoNotFound(repo_dir.to_path_buf()));
     }
 
-    // Try standard Anchor deployment path
-    let deploy_path = repo_dir.join("target/deploy/lending_program.so");
-    if deploy_path.exists() {
-        return Ok(deploy_path);
+    let names = program_file_names(repo_dir);
+    let mut searched = Vec::new();
+
+    for dir in SEARCH_DIRS {
+        for name in &names {
+            searched.push(repo_dir.join(dir).join(format!("{}.so", name)));
+        }
+    }
+
+    let mut so_files = Vec::new();
+    find_so_files_recursive(&repo_dir.join("target"), &mut so_files);
+    for path in so_files {
+        let matches_name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| names.iter().any(|name| name == stem));
+        if matches_name && !searched.contains(&path) {
+            searched.push(path);
+        }
     }
 
-    // Try SBF release path
-    let sbf_path = repo_dir.join("target/sbf-solana-solana/release/lending_program.so");
-    if sbf_path.exists() {
-        return Ok(sbf_path);
+    let mut rejected = Vec::new();
+    let mut usable = Vec::new();
+    for path in searched {
+        match sbf_elf_rejection(&path) {
+            Some(reason) => rejected.push((path, reason)),
+            None => usable.push(path),
+        }
     }
 
-    // Try artifacts directory
-    let artifacts_path = repo_dir.join("artifacts/lending_program.so");
-    if artifacts_path.exists() {
-        return Ok(artifacts_path);
+    usable
+        .into_iter()
+        .max_by_key(|path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .ok_or(ProgramLoadError::ProgramNotFound(rejected))
+}
+
+/// File stems the program SO file may have, most specific first.
+fn program_file_names(repo_dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+
+    let anchor_name =
+        find_lending_program_id(repo_dir).ok().map(|source| source.key.replace('-', "_"));
+    if let Some(name) = &anchor_name {
+        names.push(name.clone());
     }
 
-    // Try to find any .so file in the target directory
-    if let Some(so_file) = find_so_file_in_target(repo_dir) {
-        return Ok(so_file);
+    let crates = program_crates(repo_dir);
+    for (package, lib) in &crates {
+        if crates.len() == 1 || anchor_name.as_ref() == Some(package) {
+            names.push(lib.clone());
+        }
     }
 
-    Err(ProgramLoadError::ProgramNotFound)
+    names.push("lending_program".to_string());
+
+    let mut unique = Vec::new();
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+/// Package and library names of the crates under `programs/`, with hyphens
+/// replaced by underscores as in the compiled file name.
+fn program_crates(repo_dir: &Path) -> Vec<(String, String)> {
+    let Ok(entries) = std::fs::read_dir(repo_dir.join("programs")) else {
+        return Vec::new();
+    };
+
+    let mut crates = Vec::new();
+    for entry in entries.flatten() {
+        let Ok(content) = std::fs::read_to_string(entry.path().join("Cargo.toml")) else {
+            continue;
+        };
+        let Ok(manifest) = content.parse::<toml::Table>() else {
+            continue;
+        };
+        let name_of = |section: &str| {
+            manifest
+                .get(section)
+                .and_then(|section| section.get("name"))
+                .and_then(toml::Value::as_str)
+                .map(|name| name.replace('-', "_"))
+        };
+        if let Some(package) = name_of("package") {
+            let lib = name_of("lib").unwrap_or_else(|| package.clone());
+            crates.push((package, lib));
+        }
+    }
+    crates
+}
+
+/// Why `path` is not an ELF object for the BPF or SBF machine.
+///
+/// # Returns
+///
+/// * `None` - If it is one
+/// * `Some(String)` - The reason it is rejected
+fn sbf_elf_rejection(path: &Path) -> Option<String> {
+    if !path.exists() {
+        return Some("missing".to_string());
+    }
+    let mut header = [0u8; 20];
+    if let Err(err) = std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        return Some(match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => "not an ELF file".to_string(),
+            _ => format!("unreadable: {}", err),
+        });
+    }
+    if header[..4] != *b"\x7fELF" {
+        return Some("not an ELF file".to_string());
+    }
+    let machine = u16::from_le_bytes([header[18], header[19]]);
+    if machine != EM_BPF && machine != EM_SBF {
+        return Some(format!("wrong machine type {}, expected SBF or BPF", machine));
+    }
+    None
+}
+
+/// Recursively collect the .so files in a directory, without following
+/// symbolic links.
+fn find_so_files_recursive(dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            find_so_files_recursive(&path, found);
+        } else if path.extension().is_some_and(|ext| ext == "so") {
+            found.push(path);
+        }
+    }
+}
+
+/// Environment variable naming the program to look up in Anchor.toml.
+pub const PROGRAM_NAME_ENV: &str = "STACKCLASS_LENDING_PROGRAM_NAME";
+
+/// Program names looked up when [`PROGRAM_NAME_ENV`] is not set.
+const DEFAULT_PROGRAM_NAMES: [&str; 2] = ["lending_program", "lending-program"];
+
+/// Anchor.toml clusters, in order of precedence.
+const CLUSTER_PRECEDENCE: [&str; 4] = ["localnet", "devnet", "testnet", "mainnet"];
+
+/// A program ID and the Anchor.toml entry it was read from.
+#[derive(Debug, Clone)]
+pub struct ProgramIdSource {
+    pub program_id: Pubkey,
+    /// The section the ID was found in, e.g. `programs.localnet`.
+    pub section: String,
+    /// The key the ID was found under, e.g. `lending_program`.
+    pub key: String,
+}
+
+impl std::fmt::Display for ProgramIdSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} from [{}] {}", self.program_id, self.section, self.key)
+    }
 }
 
 /// Load the lending program ID from Anchor.toml.
 ///
-/// This function attempts to parse the program ID from the `programs.*`
-/// section in Anchor.toml.
+/// See [`find_lending_program_id`] for how the entry is chosen.
 ///
 /// # Arguments
 ///
@@ -139,6 +320,27 @@ pub fn load_lending_program(repo_dir: &Path) -> Result<PathBuf, ProgramLoadError
 /// * `Ok(Pubkey)` - The program ID
 /// * `Err(ProgramLoadError)` - If the program ID cannot be found or parsed
 pub fn load_lending_program_id(repo_dir: &Path) -> Result<Pubkey, ProgramLoadError> {
+    find_lending_program_id(repo_dir).map(|source| source.program_id)
+}
+
+/// Find the lending program ID in Anchor.toml and report where it came from.
+///
+/// Programs declared directly under `[programs]` are searched first, then
+/// clusters in the order localnet, devnet, testnet, mainnet, then any others
+/// alphabetically. Within a cluster the program is looked up
+/// as `lending_program` or `lending-program`, or by the name in
+/// [`PROGRAM_NAME_ENV`] if it is set. A cluster declaring a single program
+/// is used as-is; several unmatched programs are an ambiguity error.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(ProgramIdSource)` - The program ID and its section and key
+/// * `Err(ProgramLoadError)` - If the program ID cannot be found or parsed
+pub fn find_lending_program_id(repo_dir: &Path) -> Result<ProgramIdSource, ProgramLoadError> {
     if !repo_dir.exists() {
         return Err(ProgramLoadError::RepoNotFound(repo_dir.to_path_buf()));
     }
@@ -149,89 +351,173 @@ pub fn load_lending_program_id(repo_dir: &Path) -> Result<Pubkey, ProgramLoadErr
     }
 
     let content = std::fs::read_to_string(&anchor_path)?;
-    let program_id =
-        find_program_id(&content, "lending-program").ok_or(ProgramLoadError::ProgramIdNotFound)?;
+    let override_name = std::env::var(PROGRAM_NAME_ENV).ok().filter(|name| !name.is_empty());
 
-    Pubkey::from_str(&program_id).map_err(|_| ProgramLoadError::InvalidProgramId(program_id))
+    find_program_id(&content, override_name.as_deref())
 }
 
-fn find_program_id(toml: &str, program_name: &str) -> Option<String> {
-    let mut in_programs_section = false;
-
-    for raw_line in toml.lines() {
-        let line = raw_line.trim();
+/// Find the program ID in the contents of an Anchor.toml.
+///
+/// Programs are usually declared per cluster, under `[programs.<cluster>]`,
+/// but a flat `[programs]` table applies to every cluster and is searched
+/// first.
+///
+/// # Arguments
+///
+/// * `toml` - The contents of Anchor.toml
+/// * `override_name` - The program name from [`PROGRAM_NAME_ENV`], if set
+///
+/// # Returns
+///
+/// * `Ok(ProgramIdSource)` - The program ID and its section and key
+/// * `Err(ProgramLoadError)` - If the program ID cannot be found or parsed
+fn find_program_id(
+    toml: &str,
+    override_name: Option<&str>,
+) -> Result<ProgramIdSource, ProgramLoadError> {
+    let names: Vec<&str> = match override_name {
+        Some(name) => vec![name],
+        None => DEFAULT_PROGRAM_NAMES.to_vec(),
+    };
+    let table: toml::Table = toml
+        .parse()
+        .map_err(|err: toml::de::Error| ProgramLoadError::InvalidAnchorToml(err.to_string()))?;
+    let programs = table
+        .get("programs")
+        .and_then(toml::Value::as_table)
+        .ok_or(ProgramLoadError::ProgramIdNotFound)?;
 
-        if line.starts_with('[') && line.ends_with(']') {
-            let section = &line[1..line.len() - 1];
-            in_programs_section = section == "programs" || section.starts_with("programs.");
-            continue;
-        }
+    let sections = program_sections(programs);
 
-        if !in_programs_section || line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+    // Prefer the first section that declares the program by name.
+    for (section, entries) in &sections {
+        let matches: Vec<&(&String, &toml::Value)> =
+            entries.iter().filter(|(key, _)| names.contains(&key.as_str())).collect();
 
-        if let Some((key, value)) = line.split_once('=') &&
-            key.trim() == program_name
-        {
-            let value = value.trim().trim_matches('"');
-            if !value.is_empty() {
-                return Some(value.to_string());
+        match matches.as_slice() {
+            [] => continue,
+            [(key, value)] => return program_id_source(section, key, value),
+            _ => {
+                return Err(ProgramLoadError::AmbiguousProgramId(
+                    matches.iter().map(|(key, _)| format!("{}.{}", section, key)).collect(),
+                ));
             }
         }
     }
 
-    None
+    // Otherwise fall back to the only program of the preferred section,
+    // unless a specific program was asked for.
+    let Some((section, candidates)) = sections.first().filter(|_| override_name.is_none()) else {
+        return Err(ProgramLoadError::ProgramIdNotFound);
+    };
+
+    match candidates.as_slice() {
+        [] => Err(ProgramLoadError::ProgramIdNotFound),
+        [(key, value)] => program_id_source(section, key, value),
+        _ => Err(ProgramLoadError::AmbiguousProgramId(
+            candidates.iter().map(|(key, _)| format!("{}.{}", section, key)).collect(),
+        )),
+    }
 }
 
-/// Search for any .so file in the target directory.
-fn find_so_file_in_target(repo_dir: &Path) -> Option<PathBuf> {
-    let target_dir = repo_dir.join("target");
-    if !target_dir.exists() {
-        return None;
+/// The program entries of Anchor.toml's `[programs]` table, grouped by
+/// section in order of precedence.
+///
+/// Entries directly under `[programs]` form the `programs` section, which
+/// comes first. A table value is a cluster unless it has an `address`, in
+/// which case it is itself a program entry.
+fn program_sections(programs: &toml::Table) -> Vec<(String, Vec<(&String, &toml::Value)>)> {
+    let is_cluster = |value: &toml::Value| {
+        value.as_table().is_some_and(|entries| !entries.contains_key("address"))
+    };
+
+    let flat: Vec<(&String, &toml::Value)> =
+        programs.iter().filter(|(_, value)| !is_cluster(value)).collect();
+    let mut clusters: Vec<(&String, &toml::Table)> = programs
+        .iter()
+        .filter(|(_, value)| is_cluster(value))
+        .filter_map(|(cluster, value)| Some((cluster, value.as_table()?)))
+        .collect();
+    clusters.sort_by_key(|(cluster, _)| {
+        let rank = CLUSTER_PRECEDENCE.iter().position(|known| *known == cluster.as_str());
+        (rank.unwrap_or(CLUSTER_PRECEDENCE.len()), cluster.to_string())
+    });
+
+    let mut sections = Vec::new();
+    if !flat.is_empty() {
+        sections.push(("programs".to_string(), flat));
     }
+    for (cluster, entries) in clusters {
+        sections.push((format!("programs.{}", cluster), entries.iter().collect()));
+    }
+    sections
+}
 
-    // Search recursively for .so files
-    let mut found = Vec::new();
-    if let Ok(entries) = std::fs::read_dir(&target_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                if let Some(found_in_subdir) = find_so_file_recursive(&path) {
-                    found.push(found_in_subdir);
-                }
-            } else if path.extension().is_some_and(|ext| ext == "so") {
-                found.push(path);
-            }
+/// Parse a program entry, either `name = "<id>"` or
+/// `name = { address = "<id>", ... }`.
+fn program_id_source(
+    section: &str,
+    key: &str,
+    value: &toml::Value,
+) -> Result<ProgramIdSource, ProgramLoadError> {
+    let address = match value {
+        toml::Value::String(address) => address.as_str(),
+        toml::Value::Table(entry) => entry
+            .get("address")
+            .and_then(toml::Value::as_str)
+            .ok_or_else(|| ProgramLoadError::InvalidProgramId(format!("[{}] {}", section, key)))?,
+        other => {
+            return Err(ProgramLoadError::InvalidProgramId(format!(
+                "[{}] {} = {}",
+                section, key, other
+            )));
         }
-    }
+    };
 
-    // Return the first found .so file
-    found.into_iter().next()
+    let program_id = Pubkey::from_str(address).map_err(|_| {
+        ProgramLoadError::InvalidProgramId(format!("[{}] {} = {}", section, key, address))
+    })?;
+
+    Ok(ProgramIdSource { program_id, section: section.to_string(), key: key.to_string() })
 }
 
-/// Recursively search for .so files in a directory.
-fn find_so_file_recursive(dir: &Path) -> Option<PathBuf> {
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                if let Some(found) = find_so_file_recursive(&path) {
-                    return Some(found);
-                }
-            } else if path.extension().is_some_and(|ext| ext == "so") {
-                return Some(path);
-            }
+/// Find the program's deploy keypair, `target/deploy/<program>-keypair.json`,
+/// and return its public key.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(Some((PathBuf, Pubkey)))` - The keypair file and its public key
+/// * `Ok(None)` - If there is no deploy keypair
+/// * `Err(ProgramLoadError)` - If the keypair file cannot be read
+pub fn find_deploy_keypair(repo_dir: &Path) -> Result<Option<(PathBuf, Pubkey)>, ProgramLoadError> {
+    for name in program_file_names(repo_dir) {
+        let path = repo_dir.join("target/deploy").join(format!("{}-keypair.json", name));
+        if !path.exists() {
+            continue;
         }
+
+        let content = std::fs::read_to_string(&path)?;
+        let invalid =
+            |msg: String| ProgramLoadError::InvalidKeypair(format!("{}: {}", path.display(), msg));
+        let bytes: Vec<u8> =
+            serde_json::from_str(&content).map_err(|err| invalid(err.to_string()))?;
+        // A keypair file holds the 32-byte secret key followed by the public key.
+        let public: [u8; 32] = bytes
+            .get(32..)
+            .and_then(|public| public.try_into().ok())
+            .ok_or_else(|| invalid(format!("expected 64 bytes, found {}", bytes.len())))?;
+        return Ok(Some((path, Pubkey::new_from_array(public))));
     }
-    None
+
+    Ok(None)
 }
 
 /// Load the program ELF bytes from a file path.
 ///
-/// This is a wrapper around Mollusk's file loading functionality that
-/// provides better error messages.
-///
 /// # Arguments
 ///
 /// * `path` - Path to the program SO file
@@ -240,8 +526,185 @@ fn find_so_file_recursive(dir: &Path) -> Option<PathBuf> {
 ///
 /// * `Ok(Vec<u8>)` - The program ELF bytes
 /// * `Err(ProgramLoadError)` - If the file cannot be read
-#[allow(dead_code)]
 pub fn load_program_elf(path: &Path) -> Result<Vec<u8>, ProgramLoadError> {
-    let elf = file::load_program_elf(path.to_str().unwrap());
-    Ok(elf)
+    Ok(std::fs::read(path)?)
+}
+
+/// Facts about a program ELF checked before any stage runs.
+#[derive(Debug, Clone)]
+pub struct ElfInfo {
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: usize,
+    /// SBF version from the ELF header flags (0 for legacy BPF programs).
+    pub sbf_version: u32,
+}
+
+impl std::fmt::Display for ElfInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} bytes, SBF v{})", self.path.display(), self.size, self.sbf_version)
+    }
+}
+
+/// Check that `elf` is a 64-bit little-endian BPF/SBF shared object.
+///
+/// # Arguments
+///
+/// * `path` - Path the ELF was read from, for reporting
+/// * `elf` - The program ELF bytes
+///
+/// # Returns
+///
+/// * `Ok(ElfInfo)` - The program's size and SBF version
+/// * `Err(ProgramLoadError)` - An `ElfLoadError` describing the problem
+pub fn inspect_program_elf(path: &Path, elf: &[u8]) -> Result<ElfInfo, ProgramLoadError> {
+    let error =
+        |msg: String| ProgramLoadError::ElfLoadError(format!("{}: {}", path.display(), msg));
+
+    if elf.len() < 64 || elf[..4] != *b"\x7fELF" {
+        return Err(error("not an ELF file".to_string()));
+    }
+    if elf[4] != 2 || elf[5] != 1 {
+        return Err(error("not a 64-bit little-endian ELF".to_string()));
+    }
+
+    let machine = u16::from_le_bytes([elf[18], elf[19]]);
+    if machine != EM_BPF && machine != EM_SBF {
+        return Err(error(format!(
+            "ELF machine type {} is not BPF ({}) or SBF ({}); was it built with `anchor build`?",
+            machine, EM_BPF, EM_SBF
+        )));
+    }
+
+    let elf_type = u16::from_le_bytes([elf[16], elf[17]]);
+    if elf_type != ET_DYN {
+        return Err(error(format!("ELF type {} is not a shared object", elf_type)));
+    }
+
+    let sbf_version = u32::from_le_bytes([elf[48], elf[49], elf[50], elf[51]]);
+    Ok(ElfInfo { path: path.to_path_buf(), size: elf.len(), sbf_version })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENDING_ID: &str = "4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi";
+    const OTHER_ID: &str = "8qbHbw2BbbTHBW1sbeqakYXVKRQM8Ne7pLK7m6CVfeR";
+
+    fn source(toml: &str) -> Result<ProgramIdSource, ProgramLoadError> {
+        find_program_id(toml, None)
+    }
+
+    #[test]
+    fn finds_program_by_name_in_cluster() {
+        let toml = format!(
+            "[programs.localnet]\nlending_program = \"{}\"\nother = \"{}\"\n",
+            LENDING_ID, OTHER_ID
+        );
+        let found = source(&toml).unwrap();
+        assert_eq!(found.program_id, Pubkey::from_str(LENDING_ID).unwrap());
+        assert_eq!(found.section, "programs.localnet");
+        assert_eq!(found.key, "lending_program");
+    }
+
+    #[test]
+    fn prefers_localnet_over_other_clusters() {
+        let toml = format!(
+            "[programs.devnet]\nlending_program = \"{}\"\n\n[programs.localnet]\n\
+             lending_program = \"{}\"\n",
+            OTHER_ID, LENDING_ID
+        );
+        let found = source(&toml).unwrap();
+        assert_eq!(found.program_id, Pubkey::from_str(LENDING_ID).unwrap());
+        assert_eq!(found.section, "programs.localnet");
+    }
+
+    #[test]
+    fn finds_program_in_flat_programs_table() {
+        let toml = format!("[programs]\nlending-program = \"{}\"\n", LENDING_ID);
+        let found = source(&toml).unwrap();
+        assert_eq!(found.program_id, Pubkey::from_str(LENDING_ID).unwrap());
+        assert_eq!(found.section, "programs");
+        assert_eq!(found.key, "lending-program");
+    }
+
+    #[test]
+    fn reads_address_from_program_table() {
+        let toml = format!(
+            "[programs.localnet]\nlending_program = {{ address = \"{}\", idl = \"x.json\" }}\n",
+            LENDING_ID
+        );
+        let found = source(&toml).unwrap();
+        assert_eq!(found.program_id, Pubkey::from_str(LENDING_ID).unwrap());
+    }
+
+    #[test]
+    fn falls_back_to_the_only_program() {
+        let toml = format!("[programs.localnet]\nmy_lending = \"{}\"\n", LENDING_ID);
+        let found = source(&toml).unwrap();
+        assert_eq!(found.key, "my_lending");
+    }
+
+    #[test]
+    fn several_unmatched_programs_are_ambiguous() {
+        let toml =
+            format!("[programs.localnet]\nfirst = \"{}\"\nsecond = \"{}\"\n", LENDING_ID, OTHER_ID);
+        assert!(matches!(source(&toml), Err(ProgramLoadError::AmbiguousProgramId(_))));
+    }
+
+    #[test]
+    fn override_name_selects_program() {
+        let toml =
+            format!("[programs.localnet]\nfirst = \"{}\"\nsecond = \"{}\"\n", LENDING_ID, OTHER_ID);
+        let found = find_program_id(&toml, Some("second")).unwrap();
+        assert_eq!(found.program_id, Pubkey::from_str(OTHER_ID).unwrap());
+        assert!(matches!(
+            find_program_id(&toml, Some("missing")),
+            Err(ProgramLoadError::ProgramIdNotFound)
+        ));
+    }
+
+    #[test]
+    fn invalid_program_id_names_its_entry() {
+        let toml = "[programs.localnet]\nlending_program = \"not-a-key\"\n";
+        match source(toml) {
+            Err(ProgramLoadError::InvalidProgramId(entry)) => {
+                assert!(entry.contains("[programs.localnet] lending_program"));
+            }
+            other => panic!("expected InvalidProgramId, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejected_candidates_have_a_reason() {
+        let dir = std::env::temp_dir().join(format!("lending-tester-elf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut header = [0u8; 20];
+        header[..4].copy_from_slice(b"\x7fELF");
+
+        let not_elf = dir.join("not_elf.so");
+        std::fs::write(&not_elf, b"#!/bin/sh\n").unwrap();
+        let x86 = dir.join("x86.so");
+        header[18..].copy_from_slice(&62u16.to_le_bytes());
+        std::fs::write(&x86, header).unwrap();
+        let sbf = dir.join("sbf.so");
+        header[18..].copy_from_slice(&EM_SBF.to_le_bytes());
+        std::fs::write(&sbf, header).unwrap();
+
+        assert_eq!(sbf_elf_rejection(&dir.join("missing.so")).as_deref(), Some("missing"));
+        assert_eq!(sbf_elf_rejection(&not_elf).as_deref(), Some("not an ELF file"));
+        assert!(sbf_elf_rejection(&x86).unwrap().contains("wrong machine type 62"));
+        assert_eq!(sbf_elf_rejection(&sbf), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_programs_table_is_not_found() {
+        assert!(matches!(
+            source("[provider]\ncluster = \"localnet\"\n"),
+            Err(ProgramLoadError::ProgramIdNotFound)
+        ));
+    }
 }
@@ -14,24 +14,42 @@
 
 //! Test context module for managing state during testing.
 
+use crate::{
+    assertions::Expectation,
+    compute::{self, ComputeUsage},
+    invariants,
+    mollusk::{
+        account_diff::diff_accounts,
+        attacker::{self, AttackerOutcome, AttackerState},
+    },
+    report,
+};
 use mollusk_svm::{
     Mollusk,
     result::{Check, InstructionResult},
 };
 use solana_account::Account;
+use solana_clock::Clock;
 use solana_instruction::Instruction;
 use solana_instruction_error::InstructionError;
+use solana_program_option::COption;
+use solana_program_pack::Pack;
 use solana_pubkey::Pubkey;
-use std::collections::HashMap;
+use solana_svm_log_collector::LogCollector;
+use spl_token_interface::state::{Account as TokenAccount, AccountState, Mint};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 /// Error type for test context operations.
 #[derive(Debug)]
 pub enum TestContextError {
     ExecutionError(String),
+    /// An instruction returned an error.
+    InstructionFailed(InstructionError),
     #[allow(dead_code)]
     ValidationError(String),
     #[allow(dead_code)]
     AccountNotFound(String),
+    SnapshotNotFound(SnapshotId),
 }
 
 impl std::fmt::Display for TestContextError {
@@ -40,8 +58,12 @@ impl std::fmt::Display for TestContextError {
             TestContextError::ExecutionError(msg) => {
                 write!(f, "Instruction execution failed: {}", msg)
             }
+            TestContextError::InstructionFailed(err) => {
+                write!(f, "Instruction execution failed: {:?}", err)
+            }
             TestContextError::ValidationError(msg) => write!(f, "Validation failed: {}", msg),
             TestContextError::AccountNotFound(msg) => write!(f, "Account not found: {}", msg),
+            TestContextError::SnapshotNotFound(id) => write!(f, "Snapshot not found: {}", id.0),
         }
     }
 }
@@ -50,7 +72,7 @@ impl std::error::Error for TestContextError {}
 
 impl From<InstructionError> for TestContextError {
     fn from(err: InstructionError) -> Self {
-        TestContextError::ExecutionError(format!("{:?}", err))
+        TestContextError::InstructionFailed(err)
     }
 }
 
@@ -60,6 +82,16 @@ impl From<crate::mollusk::ProgramLoadError> for TestContextError {
     }
 }
 
+/// Identifies a snapshot taken with [`LendingTestContext::snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotId(usize);
+
+/// The account state and clock at the time of a snapshot.
+struct Snapshot {
+    accounts: HashMap<Pubkey, Account>,
+    clock: Clock,
+}
+
 /// A test context for the lending program.
 ///
 /// This struct manages the state of accounts during testing and provides
@@ -71,6 +103,16 @@ pub struct LendingTestContext {
     accounts: HashMap<Pubkey, Account>,
     /// The program ID being tested.
     program_id: Pubkey,
+    /// The compute units consumed by every executed instruction.
+    compute_usage: Vec<ComputeUsage>,
+    /// Snapshots taken so far, indexed by [`SnapshotId`].
+    snapshots: Vec<Snapshot>,
+    /// Whether to check the protocol invariants after every instruction.
+    check_invariants: bool,
+    /// The attacker program's state, once it is registered.
+    attacker: Option<Rc<RefCell<AttackerState>>>,
+    /// The program logs of the last executed instruction.
+    last_logs: RefCell<Vec<String>>,
 }
 
 impl LendingTestContext {
@@ -84,8 +126,29 @@ impl LendingTestContext {
     /// # Returns
     ///
     /// * `Ok(LendingTestContext)` - A new test context
-    pub fn new(mollusk: Mollusk, program_id: Pubkey) -> Result<Self, TestContextError> {
-        Ok(Self { mollusk, accounts: HashMap::new(), program_id })
+    pub fn new(mut mollusk: Mollusk, program_id: Pubkey) -> Result<Self, TestContextError> {
+        // Failed checks are reported as errors with an account diff rather
+        // than as a panic inside Mollusk.
+        mollusk.config.panic = false;
+        // Program logs are collected for the running case's report.
+        mollusk.logger = Some(LogCollector::new_ref());
+        Ok(Self {
+            mollusk,
+            accounts: HashMap::new(),
+            program_id,
+            compute_usage: Vec::new(),
+            snapshots: Vec::new(),
+            check_invariants: false,
+            attacker: None,
+            last_logs: RefCell::default(),
+        })
+    }
+
+    /// Check the protocol invariants in [`crate::invariants`] after every
+    /// instruction executed with [`Self::execute_instruction`],
+    /// [`Self::execute_and_validate`] or [`Self::execute_and_expect`].
+    pub fn enable_invariant_checks(&mut self) {
+        self.check_invariants = true;
     }
 
     /// Get the program ID.
@@ -113,7 +176,6 @@ impl LendingTestContext {
     ///
     /// * `Some(Account)` - The account data if it exists
     /// * `None` - If the account does not exist
-    #[allow(dead_code)]
     pub fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
         self.accounts.get(pubkey).cloned()
     }
@@ -132,24 +194,49 @@ impl LendingTestContext {
         &mut self,
         instruction: &Instruction,
     ) -> Result<(), TestContextError> {
-        let result: InstructionResult =
-            self.mollusk.process_instruction(instruction, &self.get_account_list());
+        let before = self.check_invariants.then(|| self.accounts.clone());
+        let result = self.process_instruction(instruction);
 
         // Check if execution was successful
-        if result.program_result.is_err() {
-            return Err(TestContextError::ExecutionError(format!("{:?}", result.program_result)));
+        if let Err(err) = result.raw_result {
+            return Err(TestContextError::InstructionFailed(err));
         }
 
+        self.verify_invariants(before.as_ref())
+    }
+
+    /// Execute an instruction and return the raw Mollusk result.
+    ///
+    /// The account state is only updated if the instruction succeeded. Use
+    /// this instead of [`Self::execute_instruction`] when the kind of
+    /// failure matters.
+    ///
+    /// # Arguments
+    ///
+    /// * `instruction` - The instruction to execute
+    ///
+    /// # Returns
+    ///
+    /// * `InstructionResult` - The result of the execution
+    pub fn process_instruction(&mut self, instruction: &Instruction) -> InstructionResult {
+        let result = self.run_instruction(instruction);
+        self.record_compute_units(instruction, &result);
+
         // Update account state from the result
-        for (pubkey, account) in result.resulting_accounts {
-            self.accounts.insert(pubkey, account);
+        if result.raw_result.is_ok() {
+            for (pubkey, account) in &result.resulting_accounts {
+                self.accounts.insert(*pubkey, account.clone());
+            }
         }
 
-        Ok(())
+        result
     }
 
     /// Execute an instruction and validate the result.
     ///
+    /// If a check fails, the error lists the changes the instruction made to
+    /// every account it touched.
+    ///
     /// # Arguments
     ///
     /// * `instruction` - The instruction to execute
@@ -165,15 +252,21 @@ impl LendingTestContext {
         instruction: &Instruction,
         checks: &[Check],
     ) -> Result<(), TestContextError> {
-        let result: InstructionResult = self.mollusk.process_and_validate_instruction(
-            instruction,
-            &self.get_account_list(),
-            checks,
-        );
+        let before = self.check_invariants.then(|| self.accounts.clone());
+        let result = self.run_instruction(instruction);
+        self.record_compute_units(instruction, &result);
+
+        if !result.run_checks(checks, &self.mollusk.config, &self.mollusk) {
+            return Err(TestContextError::ValidationError(format!(
+                "instruction result did not match the expected checks ({:?}); account changes:\n{}",
+                result.program_result,
+                diff_accounts(&self.accounts, &result.resulting_accounts)
+            )));
+        }
 
         // Check if execution was successful
-        if result.program_result.is_err() {
-            return Err(TestContextError::ExecutionError(format!("{:?}", result.program_result)));
+        if let Err(err) = result.raw_result {
+            return Err(TestContextError::InstructionFailed(err));
         }
 
         // Update account state from the result
@@ -181,6 +274,182 @@ impl LendingTestContext {
             self.accounts.insert(pubkey, account);
         }
 
+        self.verify_invariants(before.as_ref())
+    }
+
+    /// Execute an instruction and evaluate every expectation on its outcome.
+    ///
+    /// All expectations are evaluated, and any failures are reported
+    /// together with the changes the instruction made to its accounts. The
+    /// account state is only updated if the instruction succeeded.
+    ///
+    /// # Arguments
+    ///
+    /// * `instruction` - The instruction to execute
+    /// * `expectations` - The expectations to evaluate
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(InstructionResult)` - The instruction's result, if every expectation held
+    /// * `Err(TestContextError)` - Listing every failed expectation
+    pub fn execute_and_expect(
+        &mut self,
+        instruction: &Instruction,
+        expectations: &[Expectation],
+    ) -> Result<InstructionResult, TestContextError> {
+        let before = self.accounts.clone();
+        let result = self.process_instruction(instruction);
+
+        let failures: Vec<String> = expectations
+            .iter()
+            .filter_map(|expectation| {
+                expectation.evaluate(&result.raw_result, |pubkey| self.get_account(pubkey)).err()
+            })
+            .collect();
+        if failures.is_empty() {
+            self.verify_invariants(self.check_invariants.then_some(&before))?;
+            return Ok(result);
+        }
+
+        Err(TestContextError::ValidationError(format!(
+            "{} of {} expectations failed:\n  - {}\naccount changes:\n{}",
+            failures.len(),
+            expectations.len(),
+            failures.join("\n  - "),
+            diff_accounts(&before, &result.resulting_accounts)
+        )))
+    }
+
+    /// Get the compute units consumed by every instruction executed so far,
+    /// in execution order.
+    #[allow(dead_code)]
+    pub fn compute_usage(&self) -> &[ComputeUsage] {
+        &self.compute_usage
+    }
+
+    /// Check the protocol invariants against the state before an
+    /// instruction, if enabled.
+    fn verify_invariants(
+        &self,
+        before: Option<&HashMap<Pubkey, Account>>,
+    ) -> Result<(), TestContextError> {
+        let Some(before) = before else {
+            return Ok(());
+        };
+        let violations = invariants::violations(&self.program_id, before, &self.accounts);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(TestContextError::ValidationError(format!(
+            "protocol invariants violated:\n  - {}\naccount changes:\n{}",
+            violations.join("\n  - "),
+            diff_accounts(before, &self.get_account_list())
+        )))
+    }
+
+    /// Run an instruction in Mollusk against the current accounts, with
+    /// this context's attacker state installed, and capture its program logs
+    /// for the running case's report.
+    fn run_instruction(&self, instruction: &Instruction) -> InstructionResult {
+        let accounts = self.get_account_list();
+        let result = attacker::run_with(self.attacker.as_ref(), || {
+            self.mollusk.process_instruction(instruction, &accounts)
+        });
+        if let Some(logger) = &self.mollusk.logger {
+            let logs = std::mem::take(&mut *logger.borrow_mut()).into_messages();
+            report::capture(logs.clone());
+            *self.last_logs.borrow_mut() = logs;
+        }
+        result
+    }
+
+    /// The program whose failure failed the last instruction, read from its
+    /// logs: the first program to log `failed`, which is the innermost one.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(Pubkey)` - The failing program
+    /// * `None` - If the instruction succeeded or its logs name no failure
+    pub fn failing_program(&self) -> Option<Pubkey> {
+        self.last_logs.borrow().iter().find_map(|line| {
+            let (program, _) = line.strip_prefix("Program ")?.split_once(" failed: ")?;
+            program.parse().ok()
+        })
+    }
+
+    /// Record the compute units an instruction consumed, both on this
+    /// context and for the running stage.
+    fn record_compute_units(&mut self, instruction: &Instruction, result: &InstructionResult) {
+        let usage = compute::record(&instruction.data, result.compute_units_consumed);
+        self.compute_usage.push(usage);
+    }
+
+    /// Register the in-process attacker program and add its program account.
+    ///
+    /// See [`crate::mollusk::attacker`] for what the attacker does when it
+    /// is invoked. It calls back into this context's lending program.
+    pub fn add_attacker_program(&mut self) {
+        attacker::add_attacker_program(&mut self.mollusk);
+        self.add_account(attacker::ATTACKER_PROGRAM_ID, attacker::attacker_program_account());
+        self.attacker = Some(AttackerState::new(self.program_id));
+    }
+
+    /// What the attacker program observed during the last instruction.
+    ///
+    /// # Returns
+    ///
+    /// * `AttackerOutcome` - The outcome, empty if the attacker is not registered
+    pub fn attacker_outcome(&self) -> AttackerOutcome {
+        self.attacker.as_ref().map(|state| state.borrow().outcome()).unwrap_or_default()
+    }
+
+    /// Get the clock's current unix timestamp.
+    pub fn unix_timestamp(&self) -> i64 {
+        self.mollusk.sysvars.clock.unix_timestamp
+    }
+
+    /// Set the clock's unix timestamp used by subsequent instructions.
+    ///
+    /// # Arguments
+    ///
+    /// * `unix_timestamp` - The new unix timestamp
+    pub fn set_unix_timestamp(&mut self, unix_timestamp: i64) {
+        self.mollusk.sysvars.clock.unix_timestamp = unix_timestamp;
+    }
+
+    /// Save the current account state and clock.
+    ///
+    /// A snapshot can be restored any number of times, so a stage can set up
+    /// a position once and try several instructions from it.
+    ///
+    /// # Returns
+    ///
+    /// * `SnapshotId` - The ID to pass to [`Self::restore`]
+    pub fn snapshot(&mut self) -> SnapshotId {
+        self.snapshots.push(Snapshot {
+            accounts: self.accounts.clone(),
+            clock: self.mollusk.sysvars.clock.clone(),
+        });
+        SnapshotId(self.snapshots.len() - 1)
+    }
+
+    /// Roll the account state and clock back to a snapshot.
+    ///
+    /// Accounts created since the snapshot are removed. Recorded compute
+    /// usage and later snapshots are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The snapshot to restore
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the snapshot was restored
+    /// * `Err(TestContextError)` - If no snapshot has this ID
+    pub fn restore(&mut self, id: SnapshotId) -> Result<(), TestContextError> {
+        let snapshot = self.snapshots.get(id.0).ok_or(TestContextError::SnapshotNotFound(id))?;
+        self.accounts = snapshot.accounts.clone();
+        self.mollusk.sysvars.clock = snapshot.clock.clone();
         Ok(())
     }
 
@@ -222,19 +491,72 @@ impl LendingTestContext {
     /// # Returns
     ///
     /// * `Pubkey` - The public key of the new token account
-    #[allow(dead_code)]
     pub fn create_token_account(&mut self, owner: Pubkey, mint: Pubkey, amount: u64) -> Pubkey {
         let pubkey = Pubkey::new_unique();
-        let mut data = vec![0; 82]; // Token account size
+        self.create_token_account_at(pubkey, owner, mint, amount);
+        pubkey
+    }
 
-        // Set owner (offset 32)
-        data[32..64].copy_from_slice(owner.as_ref());
+    /// Create an initialized token account at a fixed address, such as an
+    /// associated token account or a PDA.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the token account
+    /// * `owner` - The owner of the token account
+    /// * `mint` - The mint address
+    /// * `amount` - Initial token amount
+    pub fn create_token_account_at(
+        &mut self,
+        address: Pubkey,
+        owner: Pubkey,
+        mint: Pubkey,
+        amount: u64,
+    ) {
+        let token_account = TokenAccount {
+            mint,
+            owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(token_account, &mut data)
+            .expect("token account fits in TokenAccount::LEN bytes");
 
-        // Set mint (offset 0)
-        data[0..32].copy_from_slice(mint.as_ref());
+        let account = Account {
+            lamports: solana_rent::Rent::default().minimum_balance(data.len()),
+            data,
+            owner: spl_token_interface::ID,
+            ..Default::default()
+        };
+        self.add_account(address, account);
+    }
 
-        // Set amount (offset 64, little-endian u64)
-        data[64..72].copy_from_slice(&amount.to_le_bytes());
+    /// Create an initialized SPL Token mint.
+    ///
+    /// # Arguments
+    ///
+    /// * `authority` - The mint authority
+    /// * `decimals` - The number of decimals
+    ///
+    /// # Returns
+    ///
+    /// * `Pubkey` - The public key of the new mint
+    pub fn create_mint(&mut self, authority: Pubkey, decimals: u8) -> Pubkey {
+        let pubkey = Pubkey::new_unique();
+        let mint = Mint {
+            mint_authority: COption::Some(authority),
+            supply: 0,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        let mut data = vec![0; Mint::LEN];
+        Mint::pack(mint, &mut data).expect("mint fits in Mint::LEN bytes");
 
         let account = Account {
             lamports: solana_rent::Rent::default().minimum_balance(data.len()),
@@ -253,6 +575,11 @@ impl Default for LendingTestContext {
             mollusk: Mollusk::default(),
             accounts: HashMap::new(),
             program_id: Pubkey::new_unique(),
+            compute_usage: Vec::new(),
+            snapshots: Vec::new(),
+            check_invariants: false,
+            attacker: None,
+            last_logs: RefCell::default(),
         }
     }
 }
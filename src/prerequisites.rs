@@ -0,0 +1,108 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Blame a failing stage on a broken prerequisite.
+//!
+//! When a stage fails, the stages it transitively relies on (see
+//! [`Stage::prerequisites`]) are checked in course order. A stage that
+//! already ran in this process counts with its recorded result. Any other
+//! fails if the program lacks one of its instructions, and is otherwise
+//! probed by running its test once, silently, so that neither its output nor
+//! its program logs end up in the failing case's report. The earliest
+//! failing prerequisite is named in the error, since fixing it usually fixes
+//! the later stage too.
+
+use crate::{
+    definition::{STAGES, Stage},
+    helpers::to_case_error,
+    mollusk::TestContextError,
+    probe, report,
+};
+use std::{collections::HashMap, sync::Mutex};
+use tester::{CaseError, Harness};
+
+/// Whether each stage passed, by slug, from its own run or a probe.
+static OUTCOMES: Mutex<Option<HashMap<&'static str, bool>>> = Mutex::new(None);
+
+/// Record a stage's result and, if it failed, check its prerequisites.
+///
+/// # Arguments
+///
+/// * `stage` - The stage that ran
+/// * `harness` - The harness to probe prerequisites with
+/// * `result` - The stage's result
+///
+/// # Returns
+///
+/// * `Ok(())` - If the stage passed
+/// * `Err(CaseError)` - The stage's error, prefixed with the earliest failing prerequisite
+pub fn explain(
+    stage: &'static Stage,
+    harness: &Harness,
+    result: Result<(), CaseError>,
+) -> Result<(), CaseError> {
+    remember(stage.slug, result.is_ok());
+    let Err(error) = result else {
+        return Ok(());
+    };
+
+    match prerequisites(stage).into_iter().find(|prerequisite| !passes(prerequisite, harness)) {
+        Some(prerequisite) => Err(to_case_error(TestContextError::ValidationError(format!(
+            "{} ({}) is failing; fix that first. {} failed with: {}",
+            prerequisite.title, prerequisite.slug, stage.slug, error
+        )))),
+        None => Err(error),
+    }
+}
+
+/// Every stage `stage` transitively relies on, in course order.
+fn prerequisites(stage: &Stage) -> Vec<&'static Stage> {
+    let mut pending: Vec<&str> = stage.prerequisites.to_vec();
+    let mut required: Vec<&str> = Vec::new();
+    while let Some(slug) = pending.pop() {
+        if required.contains(&slug) {
+            continue;
+        }
+        required.push(slug);
+        if let Some(prerequisite) = Stage::find(slug) {
+            pending.extend(prerequisite.prerequisites);
+        }
+    }
+    STAGES.iter().filter(|candidate| required.contains(&candidate.slug)).collect()
+}
+
+/// Whether a prerequisite works, probing it if it has not run yet.
+fn passes(stage: &'static Stage, harness: &Harness) -> bool {
+    let known = OUTCOMES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .and_then(|outcomes| outcomes.get(stage.slug).copied());
+    if let Some(passed) = known {
+        return passed;
+    }
+
+    let passed = probe::require(stage.instructions).is_ok() &&
+        report::silenced(|| stage.run(harness)).is_ok();
+    remember(stage.slug, passed);
+    passed
+}
+
+fn remember(slug: &'static str, passed: bool) {
+    OUTCOMES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .insert(slug, passed);
+}
@@ -0,0 +1,185 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Discover which instructions the program implements.
+//!
+//! Each instruction in [`LENDING_INSTRUCTIONS`] is sent once as its bare
+//! tag, with no arguments and no accounts. Anchor answers an unknown
+//! discriminator with `InstructionFallbackNotFound`, while a handler that
+//! exists fails later, deserializing its arguments
+//! (`InstructionDidNotDeserialize`) or accounts (`AccountNotEnoughKeys`).
+//! Any other result, including success, is reported as unknown rather than
+//! guessed at. The resulting capability table is logged the first time it is
+//! needed, and stages that use a missing instruction fail with a message
+//! naming it instead of an opaque error code.
+//!
+//! Native programs usually reject unknown tags and malformed arguments
+//! with the same error, so a missing instruction cannot be told apart. One
+//! that fails for lack of accounts (`NotEnoughAccountKeys`) is implemented.
+//! The errors that count as either come from the program's
+//! [`InstructionEncoding`].
+
+use crate::{
+    assertions::AnchorError,
+    encoding::{self, InstructionEncoding},
+    helpers::{LENDING_INSTRUCTIONS, create_lending_instruction, get_repo_dir, to_case_error},
+    mollusk::{TestContextError, init_test_context},
+    report,
+};
+use solana_instruction_error::InstructionError;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tester::CaseError;
+
+/// Whether the program implements an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capability {
+    /// The program has a handler for the instruction.
+    Implemented,
+    /// The program does not recognize the instruction's discriminator.
+    Missing,
+    /// The probe could not tell, with the reason.
+    Unknown(String),
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::Implemented => write!(f, "implemented"),
+            Capability::Missing => write!(f, "missing"),
+            Capability::Unknown(reason) => write!(f, "unknown ({})", reason),
+        }
+    }
+}
+
+/// Each instruction's capability, in [`LENDING_INSTRUCTIONS`] order.
+type Capabilities = Vec<(&'static str, Capability)>;
+
+/// The capabilities probed for a repository directory, if any.
+static CAPABILITIES: Mutex<Option<(PathBuf, Arc<Capabilities>)>> = Mutex::new(None);
+
+/// Probe the program once per process and log its capability table.
+///
+/// # Arguments
+///
+/// * `repo_dir` - The repository directory
+///
+/// # Returns
+///
+/// * `Ok(Arc<Capabilities>)` - Each instruction's capability
+/// * `Err(TestContextError)` - If the program cannot be loaded
+pub fn capabilities(repo_dir: &Path) -> Result<Arc<Capabilities>, TestContextError> {
+    let mut cached = CAPABILITIES.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some((dir, capabilities)) = cached.as_ref() &&
+        dir == repo_dir
+    {
+        return Ok(capabilities.clone());
+    }
+
+    let capabilities = Arc::new(probe(repo_dir)?);
+    report::log("Instruction capabilities:".to_string());
+    for (name, capability) in capabilities.iter() {
+        report::log(format!("  {:<16} {}", name, capability));
+    }
+    *cached = Some((repo_dir.to_path_buf(), capabilities.clone()));
+    Ok(capabilities)
+}
+
+/// Send every known tag with empty data and classify the result.
+fn probe(repo_dir: &Path) -> Result<Capabilities, TestContextError> {
+    let encoding = encoding::load(repo_dir)?;
+    let mut context = init_test_context(repo_dir)?;
+    let program_id = context.program_id();
+
+    Ok(LENDING_INSTRUCTIONS
+        .iter()
+        .map(|name| {
+            let Some(data) = encoding.tag(name) else {
+                return (*name, Capability::Unknown("no tag configured".to_string()));
+            };
+            let instruction = create_lending_instruction(program_id, data, vec![]);
+            let result = context.process_instruction(&instruction).raw_result;
+            (*name, classify(encoding.as_ref(), &result))
+        })
+        .collect())
+}
+
+/// Classify the result of sending a bare tag.
+fn classify(
+    encoding: &dyn InstructionEncoding,
+    result: &Result<(), InstructionError>,
+) -> Capability {
+    let unknown_instruction = encoding.unknown_instruction_error();
+    match result {
+        Err(error) if encoding.handler_errors().contains(error) => Capability::Implemented,
+        Err(error) if unknown_instruction.as_ref() == Some(error) => Capability::Missing,
+        Err(_) if unknown_instruction.is_none() => {
+            Capability::Unknown("not detectable for native programs".to_string())
+        }
+        Err(InstructionError::Custom(code))
+            if *code == AnchorError::DeclaredProgramIdMismatch.code() =>
+        {
+            Capability::Unknown("program ID mismatch".to_string())
+        }
+        Err(InstructionError::Custom(code)) if *code == AnchorError::InstructionMissing.code() => {
+            Capability::Unknown("discriminator not read".to_string())
+        }
+        Ok(()) => Capability::Unknown("succeeded without arguments or accounts".to_string()),
+        Err(error) => Capability::Unknown(format!("unexpected error {:?}", error)),
+    }
+}
+
+/// Fail if the program lacks any of the instructions a stage uses.
+///
+/// A program that cannot be probed is not blamed here; the stage runs and
+/// reports its own error.
+///
+/// # Arguments
+///
+/// * `instructions` - The instructions the stage uses
+///
+/// # Returns
+///
+/// * `Ok(())` - If every instruction is implemented, or the probe failed
+/// * `Err(CaseError)` - Naming the missing instructions
+pub fn require(instructions: &[&str]) -> Result<(), CaseError> {
+    if instructions.is_empty() {
+        return Ok(());
+    }
+    let Ok(repo_dir) = get_repo_dir() else {
+        return Ok(());
+    };
+    let Ok(capabilities) = capabilities(&repo_dir) else {
+        return Ok(());
+    };
+
+    let missing: Vec<String> = instructions
+        .iter()
+        .filter(|name| {
+            capabilities
+                .iter()
+                .any(|(probed, capability)| probed == *name && *capability == Capability::Missing)
+        })
+        .map(|name| format!("`{}`", name))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(to_case_error(TestContextError::ValidationError(format!(
+        "you haven't implemented {} yet",
+        missing.join(" and ")
+    ))))
+}
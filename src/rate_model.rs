@@ -0,0 +1,194 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interest rate model checks for the interest module.
+//!
+//! The challenge specifies a kinked borrow rate curve: the rate grows slowly
+//! up to the optimal utilization and steeply after it. These checks seed a
+//! `Bank` at several utilization points, let the program accrue interest over
+//! a fixed interval and compare the implied annual rate to the curve.
+
+use crate::{
+    encoding::instruction_data,
+    helpers::{LendingFixture, create_lending_instruction},
+    layout::Bank,
+    mollusk::TestContextError,
+};
+use solana_instruction::{AccountMeta, Instruction};
+use solana_pubkey::Pubkey;
+use std::path::Path;
+
+/// Number of seconds in the year used to annualize rates.
+pub const SECONDS_PER_YEAR: i64 = 31_536_000;
+
+/// Basis points representing 100%.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Time between the seeded `last_updated` and the accrual instruction.
+const ACCRUAL_INTERVAL: i64 = 86_400;
+
+/// Clock value the seeded banks were last updated at.
+const START_TIMESTAMP: i64 = 1_700_000_000;
+
+/// Total deposits of every seeded bank, large enough for one day of
+/// accrual to be measured with basis-point precision.
+const SAMPLE_DEPOSITS: u64 = 1_000_000_000_000_000;
+
+/// Allowed difference between the expected and measured rates.
+const TOLERANCE_BPS: u64 = 10;
+
+/// Utilization points sampled along the curve.
+const SAMPLE_POINTS: [(&str, u64); 5] =
+    [("0%", 0), ("below kink", 4_000), ("at kink", 8_000), ("above kink", 9_000), ("100%", 10_000)];
+
+/// A kinked (two-slope) borrow rate model, in basis points per year.
+#[derive(Debug, Clone, Copy)]
+pub struct RateModel {
+    pub base_rate_bps: u64,
+    pub slope1_bps: u64,
+    pub slope2_bps: u64,
+    pub optimal_utilization_bps: u64,
+}
+
+/// The rate model students are asked to implement.
+pub const CHALLENGE_RATE_MODEL: RateModel = RateModel {
+    base_rate_bps: 200,
+    slope1_bps: 400,
+    slope2_bps: 7_500,
+    optimal_utilization_bps: 8_000,
+};
+
+impl RateModel {
+    /// Annual borrow rate at the given utilization.
+    pub fn borrow_rate_bps(&self, utilization_bps: u64) -> u64 {
+        let utilization_bps = utilization_bps.min(BPS_DENOMINATOR);
+        if utilization_bps <= self.optimal_utilization_bps {
+            self.base_rate_bps + self.slope1_bps * utilization_bps / self.optimal_utilization_bps
+        } else {
+            let excess = utilization_bps - self.optimal_utilization_bps;
+            let excess_range = BPS_DENOMINATOR - self.optimal_utilization_bps;
+            self.base_rate_bps + self.slope1_bps + self.slope2_bps * excess / excess_range
+        }
+    }
+}
+
+/// Sample the program's rate model and compare it to [`CHALLENGE_RATE_MODEL`].
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(())` - If every sample is within tolerance
+/// * `Err(TestContextError)` - A table of all samples if any of them mismatch
+pub fn check_rate_curve(repo_dir: &Path) -> Result<(), TestContextError> {
+    let mut rows = Vec::new();
+    let mut mismatch = false;
+
+    for (label, utilization_bps) in SAMPLE_POINTS {
+        let expected = CHALLENGE_RATE_MODEL.borrow_rate_bps(utilization_bps);
+        let measured = match measure_borrow_rate(repo_dir, utilization_bps) {
+            Ok(measured) => {
+                mismatch |= measured.abs_diff(expected) > TOLERANCE_BPS;
+                format_bps(measured)
+            }
+            Err(TestContextError::InstructionFailed(_)) => {
+                mismatch = true;
+                "error".to_string()
+            }
+            Err(err) => return Err(err),
+        };
+        rows.push(format!("{:>12}  {:>9}  {:>9}", label, format_bps(expected), measured));
+    }
+
+    if !mismatch {
+        return Ok(());
+    }
+
+    Err(TestContextError::ValidationError(format!(
+        "borrow rate curve does not match the expected kinked model\n{:>12}  {:>9}  {:>9}\n{}",
+        "utilization",
+        "expected",
+        "measured",
+        rows.join("\n")
+    )))
+}
+
+/// Seed a bank at the given utilization and measure the annual borrow rate
+/// implied by one accrual interval.
+fn measure_borrow_rate(repo_dir: &Path, utilization_bps: u64) -> Result<u64, TestContextError> {
+    let mut fixture = LendingFixture::new_default(repo_dir)?;
+    let program_id = fixture.program_id();
+    let signer = fixture.user;
+
+    let mint = fixture.context_mut().create_mint(signer, 6);
+
+    // A bank with no debt accrues nothing, so the 0% point borrows 1 ppm.
+    let borrowed =
+        (SAMPLE_DEPOSITS / BPS_DENOMINATOR * utilization_bps).max(SAMPLE_DEPOSITS / 1_000_000);
+    let state = Bank {
+        authority: signer,
+        mint_address: mint,
+        total_deposits: SAMPLE_DEPOSITS,
+        total_deposit_shares: SAMPLE_DEPOSITS,
+        total_borrowed: borrowed,
+        total_borrowed_shares: borrowed,
+        liquidation_threshold: 8_000,
+        liquidation_bonus: 500,
+        liquidation_close_factor: 5_000,
+        max_ltv: 7_500,
+        last_updated: START_TIMESTAMP,
+        interest_rate: 0,
+    };
+    let bank = fixture.seed_bank(&state)?;
+
+    let context = fixture.context_mut();
+    context.set_unix_timestamp(START_TIMESTAMP + ACCRUAL_INTERVAL);
+
+    context.execute_instruction(&accrue_interest_instruction(program_id, signer, mint, bank)?)?;
+
+    let after = context
+        .get_account(&bank)
+        .and_then(|account| Bank::decode(&account.data))
+        .ok_or_else(|| TestContextError::AccountNotFound(format!("bank {}", bank)))?;
+
+    let accrued = u128::from(after.total_borrowed.saturating_sub(borrowed));
+    let rate = accrued * u128::from(BPS_DENOMINATOR) * SECONDS_PER_YEAR as u128 /
+        (u128::from(borrowed) * ACCRUAL_INTERVAL as u128);
+    Ok(u64::try_from(rate).unwrap_or(u64::MAX))
+}
+
+/// Build the `accrue_interest` instruction for a bank.
+fn accrue_interest_instruction(
+    program_id: Pubkey,
+    signer: Pubkey,
+    mint: Pubkey,
+    bank: Pubkey,
+) -> Result<Instruction, TestContextError> {
+    Ok(create_lending_instruction(
+        program_id,
+        instruction_data("accrue_interest", &[])?,
+        vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new(bank, false),
+        ],
+    ))
+}
+
+/// Format basis points as a percentage, e.g. `2.00%`.
+fn format_bps(bps: u64) -> String {
+    format!("{}.{:02}%", bps / 100, bps % 100)
+}
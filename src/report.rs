@@ -0,0 +1,229 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Machine-readable reports of stage results.
+//!
+//! When [`REPORT_PATH_ENV`] is set, every case's outcome, duration, compute
+//! units, error and captured log lines, including the program logs of every
+//! instruction it ran, are recorded and written to that
+//! path: as JUnit XML if it ends in `.xml`, and as JSON otherwise. The file
+//! is rewritten after each case, so it is complete even if the run stops at
+//! the first failure.
+
+use serde::Serialize;
+use std::{
+    fmt::Write as _,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+/// Environment variable holding the path of the report to write.
+pub const REPORT_PATH_ENV: &str = "STACKCLASS_REPORT_PATH";
+
+/// The recorded result of one case.
+#[derive(Debug, Clone, Serialize)]
+struct CaseReport {
+    slug: String,
+    passed: bool,
+    duration_ms: u64,
+    compute_units: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    logs: Vec<String>,
+}
+
+/// Every case recorded so far, in run order.
+static CASES: Mutex<Vec<CaseReport>> = Mutex::new(Vec::new());
+
+/// Log lines captured for the running case.
+static CASE_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Slug of the running case, whose log prefix its lines are printed with.
+static CURRENT_CASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Whether log lines are being discarded, see [`silenced`].
+static SILENCED: AtomicBool = AtomicBool::new(false);
+
+/// Print a message with the running case's log prefix and capture it for
+/// the case's report.
+///
+/// # Arguments
+///
+/// * `message` - The line to log
+pub fn log(message: String) {
+    if SILENCED.load(Ordering::Relaxed) {
+        return;
+    }
+    match CURRENT_CASE.lock().unwrap_or_else(|err| err.into_inner()).as_deref() {
+        Some(slug) => println!("[tester::#{}] {}", slug, message),
+        None => println!("{}", message),
+    }
+    CASE_LOGS.lock().unwrap_or_else(|err| err.into_inner()).push(message);
+}
+
+/// Capture lines for the running case's report without printing them, such
+/// as the logs of the programs an instruction ran.
+///
+/// # Arguments
+///
+/// * `lines` - The lines to capture
+pub fn capture(lines: impl IntoIterator<Item = String>) {
+    if SILENCED.load(Ordering::Relaxed) {
+        return;
+    }
+    if CURRENT_CASE.lock().unwrap_or_else(|err| err.into_inner()).is_none() {
+        return;
+    }
+    CASE_LOGS.lock().unwrap_or_else(|err| err.into_inner()).extend(lines);
+}
+
+/// Run `run` without printing or capturing any of its log lines, for work
+/// done on the running case's behalf that is not part of it.
+///
+/// # Arguments
+///
+/// * `run` - The work to run silently
+///
+/// # Returns
+///
+/// * `T` - The result of `run`
+pub fn silenced<T>(run: impl FnOnce() -> T) -> T {
+    let was_silenced = SILENCED.swap(true, Ordering::Relaxed);
+    let result = run();
+    SILENCED.store(was_silenced, Ordering::Relaxed);
+    result
+}
+
+/// Run a case and record its result in the report, if one is requested.
+///
+/// # Arguments
+///
+/// * `slug` - The stage slug
+/// * `run` - The case body, including its compute-unit checks
+///
+/// # Returns
+///
+/// * `Result<(), tester::CaseError>` - The case's own result, unchanged
+pub fn record(
+    slug: &str,
+    run: impl FnOnce() -> Result<(), tester::CaseError>,
+) -> Result<(), tester::CaseError> {
+    CASE_LOGS.lock().unwrap_or_else(|err| err.into_inner()).clear();
+    crate::compute::reset_last_stage_units();
+    *CURRENT_CASE.lock().unwrap_or_else(|err| err.into_inner()) = Some(slug.to_string());
+    let started = Instant::now();
+    let result = run();
+    let duration = started.elapsed();
+    *CURRENT_CASE.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    let logs = std::mem::take(&mut *CASE_LOGS.lock().unwrap_or_else(|err| err.into_inner()));
+
+    let Ok(path) = std::env::var(REPORT_PATH_ENV) else {
+        return result;
+    };
+
+    let report = CaseReport {
+        slug: slug.to_string(),
+        passed: result.is_ok(),
+        duration_ms: duration.as_millis() as u64,
+        compute_units: crate::compute::last_stage_units(),
+        error: result.as_ref().err().map(|err| err.to_string()),
+        logs,
+    };
+    let mut cases = CASES.lock().unwrap_or_else(|err| err.into_inner());
+    cases.push(report);
+    if let Err(err) = write_report(Path::new(&path), &cases) {
+        eprintln!("failed to write the report to {}: {}", path, err);
+    }
+
+    result
+}
+
+/// Write the recorded cases in the format the path's extension asks for.
+fn write_report(path: &Path, cases: &[CaseReport]) -> std::io::Result<()> {
+    let contents = if path.extension().is_some_and(|extension| extension == "xml") {
+        junit_xml(cases)
+    } else {
+        serde_json::to_string_pretty(&serde_json::json!({ "cases": cases }))
+            .map_err(std::io::Error::other)?
+    };
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+/// Render the recorded cases as a single JUnit test suite.
+fn junit_xml(cases: &[CaseReport]) -> String {
+    let seconds = |duration_ms: u64| duration_ms as f64 / 1000.0;
+    let failures = cases.iter().filter(|case| !case.passed).count();
+    let total_ms: u64 = cases.iter().map(|case| case.duration_ms).sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuite name=\"solana-lending-program-tester\" tests=\"{}\" failures=\"{}\" \
+         time=\"{:.3}\">",
+        cases.len(),
+        failures,
+        seconds(total_ms)
+    );
+    for case in cases {
+        let _ = writeln!(
+            xml,
+            "  <testcase name=\"{}\" classname=\"stages\" time=\"{:.3}\">",
+            escape(&case.slug),
+            seconds(case.duration_ms)
+        );
+        let _ = writeln!(
+            xml,
+            "    <properties><property name=\"compute_units\" value=\"{}\"/></properties>",
+            case.compute_units
+        );
+        if let Some(error) = &case.error {
+            let summary = error.lines().next().unwrap_or_default();
+            let _ = writeln!(
+                xml,
+                "    <failure message=\"{}\">{}</failure>",
+                escape(summary),
+                escape(error)
+            );
+        }
+        if !case.logs.is_empty() {
+            let _ = writeln!(xml, "    <system-out>{}</system-out>", escape(&case.logs.join("\n")));
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// Escape text for use in XML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
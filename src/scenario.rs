@@ -0,0 +1,775 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative scenario files for stage tests.
+//!
+//! A scenario is a JSON file describing the starting state (mints, wallets
+//! and their token balances, seeded `Bank` and `User` accounts, Pyth price
+//! updates and the clock) and a sequence of steps: clock warps and
+//! instructions named as in the program's IDL, each with the balances,
+//! `Bank` fields or error expected afterwards, and price changes.
+//!
+//! Scenarios live in `src/scenarios/` and are embedded into the tester, so
+//! course authors can edit them without touching the stage code. Entities are
+//! referred to by name; instruction accounts are resolved from the IDL as in
+//! the security stages, with any slot named like an entity taking that
+//! entity, and per-step `accounts` overriding both. A seeded `User` is named
+//! `user:<owner>`.
+//!
+//! Successful `deposit`, `withdraw`, `borrow`, `repay`, `liquidate` and
+//! `accrue_interest` steps are also compared with the reference model in
+//! [`crate::model`], pricing assets from the scenario's SOL/USD and USDC/USD
+//! price accounts and accruing interest with the challenge's rate model, and
+//! every instruction must keep the protocol invariants in
+//! [`crate::invariants`]. Scenarios can be run whole with [`run_scenario`] or
+//! step by step with [`ScenarioRun`], as the fuzzer does.
+
+use crate::{
+    assertions::{
+        AnchorError, Expectation, expect_account_closed, expect_bank_field, expect_error,
+        expect_failure, expect_success, expect_token_balance,
+    },
+    helpers::{LendingFixture, anchor_discriminator, create_lending_instruction},
+    idl::{Idl, load_idl, resolve_accounts},
+    layout::{Bank, User, bank_address, treasury_address, user_address},
+    model::{self, Asset, Prices},
+    mollusk::{LendingTestContext, TestContextError},
+    rate_model::CHALLENGE_RATE_MODEL,
+    security::associated_token_address,
+};
+use serde::{Deserialize, Serialize};
+use solana_account::Account;
+use solana_instruction::AccountMeta;
+use solana_instruction_error::InstructionError;
+use solana_pubkey::Pubkey;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// A deposit into a seeded bank.
+pub const BASIC_DEPOSIT: &str = include_str!("scenarios/basic_deposit.json");
+
+/// A withdrawal of part of a seeded deposit.
+pub const BASIC_WITHDRAW: &str = include_str!("scenarios/basic_withdraw.json");
+
+/// Pyth feed IDs the challenge reads prices from.
+pub const SOL_USD_FEED_ID: &str =
+    "0xef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d";
+pub const USDC_USD_FEED_ID: &str =
+    "0xeaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a";
+
+/// The Pyth receiver program that owns `PriceUpdateV2` accounts.
+const PYTH_RECEIVER_ID: Pubkey =
+    Pubkey::from_str_const("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+/// Allocated size of a `PriceUpdateV2` account.
+const PRICE_UPDATE_SIZE: usize = 134;
+
+/// Name that refers to a mint's treasury in token balance expectations.
+const TREASURY: &str = "treasury";
+
+/// A scenario: the starting state and the steps to run against it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Scenario {
+    pub name: String,
+    /// The starting unix timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mints: Vec<MintSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wallets: Vec<WalletSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub banks: Vec<BankSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prices: Vec<PriceSpec>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MintSpec {
+    pub name: String,
+    #[serde(default = "default_decimals")]
+    pub decimals: u8,
+}
+
+/// A funded wallet and its associated token account balances, by mint.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WalletSpec {
+    pub name: String,
+    #[serde(default = "default_lamports")]
+    pub lamports: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tokens: BTreeMap<String, u64>,
+}
+
+/// A seeded `Bank` for a mint; other keys set the numeric `Bank` fields.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BankSpec {
+    pub mint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+    /// Tokens held by the bank's treasury.
+    #[serde(default)]
+    pub treasury: u64,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, u64>,
+}
+
+/// A seeded `User`; other keys set the numeric `User` fields.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UserSpec {
+    pub owner: String,
+    pub mint: String,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, u64>,
+}
+
+/// A Pyth `PriceUpdateV2` account.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PriceSpec {
+    pub name: String,
+    /// The feed ID as hex, with or without a `0x` prefix.
+    pub feed_id: String,
+    pub price: i64,
+    #[serde(default)]
+    pub conf: u64,
+    pub exponent: i32,
+    /// Seconds between the price's publish time and the clock.
+    #[serde(default)]
+    pub age: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Step {
+    /// Advance the clock by this many seconds.
+    Warp {
+        warp: i64,
+    },
+    /// Republish a price account at the current clock with a new price.
+    SetPrice {
+        set_price: String,
+        price: i64,
+    },
+    Instruction(InstructionStep),
+}
+
+impl Step {
+    /// A short name for the step in error messages.
+    pub fn label(&self) -> &str {
+        match self {
+            Step::Warp { .. } => "warp",
+            Step::SetPrice { .. } => "set_price",
+            Step::Instruction(step) => &step.instruction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InstructionStep {
+    pub instruction: String,
+    pub signer: String,
+    /// The mint passed to slots named like `mint`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, u64>,
+    /// Entities to pass in specific slots, by IDL account name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "ExpectSpec::is_empty")]
+    pub expect: ExpectSpec,
+}
+
+/// What a step expects. Without an `error`, the step must succeed, unless
+/// `any_outcome` allows it to either succeed or fail.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ExpectSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorSpec>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub any_outcome: bool,
+    /// Token balances by wallet (or `treasury`), then by mint.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub token_balances: BTreeMap<String, BTreeMap<String, u64>>,
+    /// `Bank` fields by mint, then by field name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub banks: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closed: Vec<String>,
+}
+
+impl ExpectSpec {
+    /// Whether the spec only expects success.
+    fn is_empty(&self) -> bool {
+        self.error.is_none() &&
+            !self.any_outcome &&
+            self.token_balances.is_empty() &&
+            self.banks.is_empty() &&
+            self.closed.is_empty()
+    }
+}
+
+/// An expected error: a code, an Anchor error name, or `any`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ErrorSpec {
+    Code(u32),
+    Name(String),
+}
+
+fn default_decimals() -> u8 {
+    6
+}
+
+fn default_lamports() -> u64 {
+    10_000_000_000
+}
+
+/// Parse a scenario file.
+///
+/// # Arguments
+///
+/// * `source` - The scenario as JSON
+///
+/// # Returns
+///
+/// * `Ok(Scenario)` - The parsed scenario
+/// * `Err(TestContextError)` - If the JSON does not describe a scenario
+pub fn parse_scenario(source: &str) -> Result<Scenario, TestContextError> {
+    serde_json::from_str(source)
+        .map_err(|err| TestContextError::ValidationError(format!("invalid scenario: {}", err)))
+}
+
+/// A scenario being run: its starting state set up, ready for steps.
+pub struct ScenarioRun {
+    idl: Idl,
+    fixture: LendingFixture,
+    program_id: Pubkey,
+    names: HashMap<String, Pubkey>,
+    prices: HashMap<String, PriceSpec>,
+}
+
+/// What a step did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The step warped the clock or changed a price.
+    Updated,
+    /// The step ran an instruction, with this result.
+    Executed(Result<(), InstructionError>),
+}
+
+impl ScenarioRun {
+    /// Set up a scenario's starting state against the user's program.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo_dir` - Path to the user's repository directory
+    /// * `scenario` - The scenario to set up
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ScenarioRun)` - Ready to run the scenario's steps
+    /// * `Err(TestContextError)` - If the program cannot be loaded or the setup is invalid
+    pub fn setup(repo_dir: &Path, scenario: &Scenario) -> Result<Self, TestContextError> {
+        let fail = |message: String| {
+            TestContextError::ValidationError(format!("scenario `{}`: {}", scenario.name, message))
+        };
+
+        let idl = load_idl(repo_dir)?;
+        let mut fixture = LendingFixture::new_default(repo_dir)?;
+        let program_id = fixture.program_id();
+        let payer = fixture.user;
+        let mut names: HashMap<String, Pubkey> = HashMap::new();
+        let lookup = |names: &HashMap<String, Pubkey>, name: &str| {
+            names.get(name).copied().ok_or_else(|| fail(format!("unknown name `{}`", name)))
+        };
+
+        let context = fixture.context_mut();
+        if let Some(clock) = scenario.clock {
+            context.set_unix_timestamp(clock);
+        }
+        for mint in &scenario.mints {
+            names.insert(mint.name.clone(), context.create_mint(payer, mint.decimals));
+        }
+        for wallet in &scenario.wallets {
+            let owner = context.create_funded_account(wallet.lamports);
+            for (mint, amount) in &wallet.tokens {
+                let mint = lookup(&names, mint)?;
+                context.create_token_account_at(
+                    associated_token_address(&owner, &mint),
+                    owner,
+                    mint,
+                    *amount,
+                );
+            }
+            names.insert(wallet.name.clone(), owner);
+        }
+        for price in &scenario.prices {
+            let address = Pubkey::new_unique();
+            write_price(context, address, price)?;
+            names.insert(price.name.clone(), address);
+        }
+
+        for spec in &scenario.banks {
+            let mint = lookup(&names, &spec.mint)?;
+            let authority = match &spec.authority {
+                Some(name) => lookup(&names, name)?,
+                None => payer,
+            };
+            let mut bank = Bank { authority, mint_address: mint, ..Default::default() };
+            for (field, value) in &spec.fields {
+                if !bank.set_field(field, *value) {
+                    return Err(fail(format!("Bank has no numeric field `{}`", field)));
+                }
+            }
+            fixture.seed_bank(&bank)?;
+            let treasury = treasury_address(&program_id, &mint);
+            fixture.context_mut().create_token_account_at(treasury, treasury, mint, spec.treasury);
+        }
+        for spec in &scenario.users {
+            let owner = lookup(&names, &spec.owner)?;
+            let mint = lookup(&names, &spec.mint)?;
+            let mut user = User { owner, usdc_address: mint, ..Default::default() };
+            for (field, value) in &spec.fields {
+                if !user.set_field(field, *value) {
+                    return Err(fail(format!("User has no numeric field `{}`", field)));
+                }
+            }
+            let address = fixture.seed_user(&user)?;
+            names.insert(format!("user:{}", spec.owner), address);
+        }
+
+        fixture.context_mut().enable_invariant_checks();
+
+        let prices =
+            scenario.prices.iter().map(|price| (price.name.clone(), price.clone())).collect();
+        Ok(Self { idl, fixture, program_id, names, prices })
+    }
+
+    /// Run one step and check its expectations.
+    ///
+    /// # Arguments
+    ///
+    /// * `step` - The step to run
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StepOutcome)` - If the step met its expectations
+    /// * `Err(String)` - Why it did not
+    pub fn run_step(&mut self, step: &Step) -> Result<StepOutcome, String> {
+        let step = match step {
+            Step::Warp { warp } => {
+                let context = self.fixture.context_mut();
+                context.set_unix_timestamp(context.unix_timestamp() + warp);
+                return Ok(StepOutcome::Updated);
+            }
+            Step::SetPrice { set_price, price } => {
+                let spec = self
+                    .prices
+                    .get_mut(set_price)
+                    .ok_or_else(|| format!("unknown price `{}`", set_price))?;
+                spec.price = *price;
+                spec.age = 0;
+                let address = self.names[set_price];
+                write_price(self.fixture.context_mut(), address, spec)
+                    .map_err(|err| err.to_string())?;
+                return Ok(StepOutcome::Updated);
+            }
+            Step::Instruction(step) => step,
+        };
+
+        let names = &self.names;
+        let lookup =
+            |name: &str| names.get(name).copied().ok_or(format!("unknown name `{}`", name));
+        let program_id = self.program_id;
+        let instruction = self
+            .idl
+            .instruction(&step.instruction)
+            .ok_or_else(|| "the IDL has no such instruction".to_string())?;
+        let signer = lookup(&step.signer)?;
+        let mint = step.mint.as_deref().map(lookup).transpose()?;
+        let overrides = step
+            .accounts
+            .iter()
+            .map(|(slot, name)| Ok((slot.clone(), lookup(name)?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+
+        let accounts =
+            resolve_accounts(instruction, &program_id, &step.args, &overrides, |account| {
+                if account.signer {
+                    signer
+                } else if let Some(pubkey) = names.get(&account.name) {
+                    *pubkey
+                } else if let Some(mint) = mint.filter(|_| account.name.contains("mint")) {
+                    mint
+                } else {
+                    Pubkey::new_unique()
+                }
+            })?;
+        let modelled = modelled_step(step, &accounts, &program_id, signer, mint);
+        let data = instruction.data_with_args(&step.args)?;
+        let ix = create_lending_instruction(
+            program_id,
+            data,
+            accounts.into_iter().map(|(_, meta)| meta).collect(),
+        );
+        let expectations = expectations(&step.expect, names, &program_id)?;
+
+        // Steps that succeed are also checked against the reference model,
+        // starting from the program's own state before the step.
+        let prices = self.model_prices();
+        let context = self.fixture.context_mut();
+        let now = context.unix_timestamp();
+        let before = modelled.as_ref().and_then(|modelled| modelled.read_state(context));
+
+        let result =
+            context.execute_and_expect(&ix, &expectations).map_err(|err| err.to_string())?;
+
+        if result.raw_result.is_ok() &&
+            let (Some(modelled), Some(before)) = (modelled, before)
+        {
+            let after = modelled
+                .read_state(context)
+                .ok_or_else(|| "a Bank or User account was closed".to_string())?;
+            check_model(&modelled, prices.as_ref(), now, before, after)?;
+        }
+
+        Ok(StepOutcome::Executed(result.raw_result))
+    }
+
+    /// The current SOL and USDC prices, if the scenario publishes both.
+    fn model_prices(&self) -> Option<Prices> {
+        let price = |feed_id: &str| {
+            let spec = self.prices.values().find(|spec| {
+                spec.feed_id.trim_start_matches("0x") == feed_id.trim_start_matches("0x")
+            })?;
+            micro_usd(spec.price, spec.exponent)
+        };
+        Some(Prices { sol: price(SOL_USD_FEED_ID)?, usdc: price(USDC_USD_FEED_ID)? })
+    }
+}
+
+/// A Pyth price in micro-USD per whole token, as [`Prices`] holds them.
+fn micro_usd(price: i64, exponent: i32) -> Option<u64> {
+    let price = u128::try_from(price).ok()?;
+    let scaled = match exponent + 6 {
+        shift @ 0.. => price.checked_mul(10u128.checked_pow(shift.unsigned_abs())?)?,
+        shift => price / 10u128.checked_pow(shift.unsigned_abs())?,
+    };
+    u64::try_from(scaled).ok()
+}
+
+/// An instruction step the reference model predicts, with the accounts it
+/// changes.
+struct ModelledStep {
+    instruction: String,
+    /// The banks the step changes: the bank of the step's mint, or for
+    /// `liquidate` the collateral bank followed by the borrowed bank.
+    banks: Vec<Pubkey>,
+    /// The `User` account the step changes; `accrue_interest` changes none.
+    user: Option<Pubkey>,
+    /// The mint of the asset moved, or of the debt for `liquidate`.
+    mint: Pubkey,
+    /// The step's `amount` argument; `liquidate` and `accrue_interest` take
+    /// none.
+    amount: Option<u64>,
+}
+
+impl ModelledStep {
+    /// Read the step's banks and `User` account, if they all exist.
+    fn read_state(&self, context: &LendingTestContext) -> Option<(Vec<Bank>, Option<User>)> {
+        let banks = self
+            .banks
+            .iter()
+            .map(|bank| context.get_account(bank).and_then(|account| Bank::decode(&account.data)))
+            .collect::<Option<Vec<Bank>>>()?;
+        let user = match &self.user {
+            Some(user) => {
+                Some(context.get_account(user).and_then(|account| User::decode(&account.data))?)
+            }
+            None => None,
+        };
+        Some((banks, user))
+    }
+}
+
+/// Find the accounts a modelled instruction changes among its resolved
+/// account slots.
+///
+/// # Returns
+///
+/// * `Some(ModelledStep)` - If the model predicts the instruction
+/// * `None` - If it does not, or the step lacks an account or argument it needs
+fn modelled_step(
+    step: &InstructionStep,
+    accounts: &[(String, AccountMeta)],
+    program_id: &Pubkey,
+    signer: Pubkey,
+    mint: Option<Pubkey>,
+) -> Option<ModelledStep> {
+    let slot = |names: &[&str]| {
+        accounts
+            .iter()
+            .find(|(name, meta)| !meta.is_signer && names.contains(&name.as_str()))
+            .map(|(_, meta)| meta.pubkey)
+    };
+
+    match step.instruction.as_str() {
+        "deposit" | "withdraw" | "borrow" | "repay" => {
+            let mint = mint?;
+            Some(ModelledStep {
+                instruction: step.instruction.clone(),
+                banks: vec![bank_address(program_id, &mint)],
+                user: Some(user_address(program_id, &signer)),
+                mint,
+                amount: Some(*step.args.get("amount")?),
+            })
+        }
+        "accrue_interest" => {
+            let mint = mint?;
+            Some(ModelledStep {
+                instruction: step.instruction.clone(),
+                banks: vec![bank_address(program_id, &mint)],
+                user: None,
+                mint,
+                amount: None,
+            })
+        }
+        "liquidate" => {
+            let collateral = slot(&["collateral_mint", "collateralMint"])?;
+            let borrowed = slot(&["borrowed_mint", "borrowedMint"])?;
+            Some(ModelledStep {
+                instruction: step.instruction.clone(),
+                banks: vec![
+                    bank_address(program_id, &collateral),
+                    bank_address(program_id, &borrowed),
+                ],
+                user: Some(slot(&["user_account", "userAccount", "user"])?),
+                mint: borrowed,
+                amount: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Run a scenario against the user's program.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+/// * `scenario` - The scenario to run
+///
+/// # Returns
+///
+/// * `Ok(())` - If every step met its expectations
+/// * `Err(TestContextError)` - The first step that did not, and why
+pub fn run_scenario(repo_dir: &Path, scenario: &Scenario) -> Result<(), TestContextError> {
+    let mut run = ScenarioRun::setup(repo_dir, scenario)?;
+    for (index, step) in scenario.steps.iter().enumerate() {
+        run.run_step(step).map_err(|message| {
+            TestContextError::ValidationError(format!(
+                "scenario `{}`: step {} (`{}`): {}",
+                scenario.name,
+                index + 1,
+                step.label(),
+                message
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Parse and run one of the embedded scenarios.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+/// * `source` - The scenario as JSON
+///
+/// # Returns
+///
+/// * `Ok(())` - If every step met its expectations
+/// * `Err(TestContextError)` - If the scenario is invalid or a step failed
+pub fn run_embedded(repo_dir: &Path, source: &str) -> Result<(), TestContextError> {
+    run_scenario(repo_dir, &parse_scenario(source)?)
+}
+
+/// Turn a step's expectations into [`Expectation`]s.
+fn expectations(
+    spec: &ExpectSpec,
+    names: &HashMap<String, Pubkey>,
+    program_id: &Pubkey,
+) -> Result<Vec<Expectation>, String> {
+    let lookup = |name: &str| names.get(name).copied().ok_or(format!("unknown name `{}`", name));
+    let mut expectations = Vec::new();
+    match &spec.error {
+        _ if spec.any_outcome => {}
+        None => expectations.push(expect_success()),
+        Some(ErrorSpec::Name(name)) if name == "any" => expectations.push(expect_failure()),
+        Some(ErrorSpec::Name(name)) => expectations.push(expect_error(
+            AnchorError::from_name(name).ok_or(format!("unknown Anchor error `{}`", name))?,
+        )),
+        Some(ErrorSpec::Code(code)) => expectations.push(expect_error(AnchorError::Custom(*code))),
+    }
+
+    for (wallet, balances) in &spec.token_balances {
+        for (mint, amount) in balances {
+            let mint = lookup(mint)?;
+            let account = if wallet == TREASURY {
+                treasury_address(program_id, &mint)
+            } else {
+                associated_token_address(&lookup(wallet)?, &mint)
+            };
+            expectations.push(expect_token_balance(account, *amount));
+        }
+    }
+    for (mint, fields) in &spec.banks {
+        let bank = bank_address(program_id, &lookup(mint)?);
+        for (field, value) in fields {
+            expectations.push(expect_bank_field(bank, field, *value));
+        }
+    }
+    for name in &spec.closed {
+        expectations.push(expect_account_closed(lookup(name)?));
+    }
+
+    Ok(expectations)
+}
+
+/// Compare the program's state after a modelled step with the reference
+/// model's.
+///
+/// `borrow` and `liquidate` depend on prices, and are only checked when the
+/// scenario publishes both. Interest accrues up to `now`, the clock when the
+/// step ran.
+fn check_model(
+    step: &ModelledStep,
+    prices: Option<&Prices>,
+    now: i64,
+    (mut banks, user): (Vec<Bank>, Option<User>),
+    (banks_after, user_after): (Vec<Bank>, Option<User>),
+) -> Result<(), String> {
+    if let [bank] = banks.as_mut_slice() &&
+        step.instruction == "accrue_interest"
+    {
+        model::accrue_interest(bank, &CHALLENGE_RATE_MODEL, now).map_err(|err| {
+            format!("the program accrued interest the reference model cannot: {}", err)
+        })?;
+        return compare_with_model(&banks, &banks_after, None);
+    }
+    let (Some(mut user), Some(user_after)) = (user, user_after) else {
+        return Ok(());
+    };
+    let asset = if user.usdc_address == step.mint { Asset::Usdc } else { Asset::Sol };
+
+    let result = match (step.instruction.as_str(), banks.as_mut_slice(), step.amount, prices) {
+        ("deposit", [bank], Some(amount), _) => model::deposit(bank, &mut user, asset, amount),
+        ("withdraw", [bank], Some(amount), _) => model::withdraw(bank, &mut user, asset, amount),
+        ("repay", [bank], Some(amount), _) => model::repay(bank, &mut user, asset, amount),
+        ("borrow", [bank], Some(amount), Some(prices)) => {
+            model::borrow(bank, &mut user, asset, amount, prices)
+        }
+        ("liquidate", [collateral_bank, borrowed_bank], None, Some(prices)) => {
+            model::liquidate(collateral_bank, borrowed_bank, &mut user, asset, prices).map(|_| ())
+        }
+        _ => return Ok(()),
+    };
+    result.map_err(|err| {
+        format!("the program accepted an operation the reference model rejects: {}", err)
+    })?;
+
+    compare_with_model(&banks, &banks_after, Some((&user, &user_after)))
+}
+
+/// List every modelled field on which the program's banks and `User`
+/// account differ from the model's.
+fn compare_with_model(
+    banks: &[Bank],
+    banks_after: &[Bank],
+    user: Option<(&User, &User)>,
+) -> Result<(), String> {
+    let mut problems = Vec::new();
+    for (bank, bank_after) in banks.iter().zip(banks_after) {
+        problems.extend(model::mismatches(&bank.fields(), &bank_after.fields()));
+    }
+    if let Some((user, user_after)) = user {
+        problems.extend(
+            model::mismatches(&user.fields(), &user_after.fields())
+                .into_iter()
+                .map(|problem| format!("User {}", problem)),
+        );
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("state differs from the reference model:\n  - {}", problems.join("\n  - ")))
+    }
+}
+
+/// Create or overwrite a Pyth price account, published relative to the
+/// current clock.
+fn write_price(
+    context: &mut LendingTestContext,
+    address: Pubkey,
+    price: &PriceSpec,
+) -> Result<(), TestContextError> {
+    let data = price_update_data(price, context.unix_timestamp())
+        .map_err(TestContextError::ValidationError)?;
+    context.add_account(
+        address,
+        Account {
+            lamports: solana_rent::Rent::default().minimum_balance(data.len()),
+            data,
+            owner: PYTH_RECEIVER_ID,
+            ..Default::default()
+        },
+    );
+    Ok(())
+}
+
+/// Serialize a Pyth `PriceUpdateV2` account with full verification.
+fn price_update_data(price: &PriceSpec, now: i64) -> Result<Vec<u8>, String> {
+    let hex = price.feed_id.trim_start_matches("0x");
+    let feed_id = (0..hex.len())
+        .step_by(2)
+        .map(|index| hex.get(index..index + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or(format!("price `{}` has an invalid feed ID", price.name))?;
+    let publish_time = now - price.age;
+
+    let mut data = Vec::with_capacity(PRICE_UPDATE_SIZE);
+    data.extend_from_slice(&anchor_discriminator("account:PriceUpdateV2"));
+    // Write authority, then `VerificationLevel::Full`.
+    data.extend_from_slice(&[0; 32]);
+    data.push(1);
+    data.extend_from_slice(&feed_id);
+    data.extend_from_slice(&price.price.to_le_bytes());
+    data.extend_from_slice(&price.conf.to_le_bytes());
+    data.extend_from_slice(&price.exponent.to_le_bytes());
+    data.extend_from_slice(&publish_time.to_le_bytes());
+    data.extend_from_slice(&publish_time.to_le_bytes());
+    // EMA price and confidence, then the posted slot.
+    data.extend_from_slice(&price.price.to_le_bytes());
+    data.extend_from_slice(&price.conf.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.resize(PRICE_UPDATE_SIZE, 0);
+    Ok(data)
+}
@@ -0,0 +1,807 @@
+// Copyright (c) The StackClass Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Attack catalogue for the security module.
+//!
+//! Every instruction discovered in the program's IDL is replayed with
+//! tampered accounts against a state where a victim already holds a
+//! position. An attack that executes successfully is a vulnerability and is
+//! reported by instruction and account name.
+//!
+//! Each instruction is first run untampered, against the seeded state or,
+//! for instructions that create the bank or user accounts, an empty one.
+//! Its attacks run against whichever state accepted it, so a rejection is
+//! due to the tampering rather than to the setup.
+//!
+//! Account substitution ("type cosplay") attacks are applied to every
+//! non-signer slot; the account currently in the slot decides which of them
+//! apply, so no attack depends on how a student named their accounts.
+
+use crate::{
+    helpers::{LendingFixture, create_lending_instruction},
+    idl::{IdlAccount, IdlInstruction, load_idl, resolve_accounts},
+    layout::{Bank, User, bank_address, treasury_address, user_address},
+    mollusk::{TestContextError, attacker::ATTACKER_PROGRAM_ID},
+    report,
+};
+use solana_instruction::{AccountMeta, Instruction};
+use solana_instruction_error::InstructionError;
+use solana_program_pack::Pack;
+use solana_pubkey::Pubkey;
+use spl_token_interface::state::{Account as TokenAccount, Mint};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::Path,
+};
+
+/// Amount of tokens the victim has deposited in the seeded bank.
+const VICTIM_DEPOSIT: u64 = 1_000_000;
+
+/// Amount of tokens the victim has borrowed for the extreme value checks,
+/// well within the seeded bank's maximum LTV.
+const VICTIM_BORROW: u64 = 250_000;
+
+/// Instructions whose untampered run must succeed for an attack catalogue
+/// to be meaningful.
+const BASELINE_REQUIRED: &[&str] = &["deposit", "withdraw"];
+
+/// A tampering applied to an otherwise well-formed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attack {
+    /// The authority is passed without the signer flag.
+    MissingSigner,
+    /// Another user signs in place of the authority.
+    SubstitutedAuthority,
+    /// The attacker signs but passes the victim's `User` account.
+    ForeignUserAccount,
+    /// A `User` account is passed where a `Bank` is expected, or vice versa.
+    WrongAccountType,
+    /// A token account of a different mint is passed.
+    MismatchedMint,
+    /// A copy of a program account, byte for byte, owned by another program.
+    ForeignOwner,
+    /// A mint other than the bank's mint is passed.
+    FakeMint,
+}
+
+impl fmt::Display for Attack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Attack::MissingSigner => write!(f, "accepted without the authority's signature"),
+            Attack::SubstitutedAuthority => write!(f, "accepted another user as authority"),
+            Attack::ForeignUserAccount => write!(f, "accepted another user's User account"),
+            Attack::WrongAccountType => write!(f, "accepted an account of the wrong type"),
+            Attack::MismatchedMint => write!(f, "accepted a token account of a different mint"),
+            Attack::ForeignOwner => {
+                write!(f, "accepted an identical account owned by another program")
+            }
+            Attack::FakeMint => write!(f, "accepted a mint other than the bank's mint"),
+        }
+    }
+}
+
+/// An attack the program failed to reject.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub instruction: String,
+    pub account: String,
+    pub description: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` / `{}`: {}", self.instruction, self.account, self.description)
+    }
+}
+
+/// A fresh fixture in which `victim` holds a deposit in the bank for `mint`
+/// and `attacker` has an empty `User` account. The victim's associated token
+/// account and the bank's treasury hold tokens of `mint`.
+pub struct AttackSetup {
+    pub fixture: LendingFixture,
+    pub program_id: Pubkey,
+    pub victim: Pubkey,
+    pub attacker: Pubkey,
+    pub mint: Pubkey,
+}
+
+impl AttackSetup {
+    pub fn new(repo_dir: &Path) -> Result<Self, TestContextError> {
+        Self::create(repo_dir, true, 0)
+    }
+
+    /// A seeded setup in which the victim has also borrowed `borrowed`
+    /// tokens out of the treasury.
+    pub fn with_borrow(repo_dir: &Path, borrowed: u64) -> Result<Self, TestContextError> {
+        Self::create(repo_dir, true, borrowed)
+    }
+
+    /// A setup with the same wallets, mint and victim tokens, but without
+    /// the bank, treasury and `User` accounts, for instructions that
+    /// create them.
+    pub fn unseeded(repo_dir: &Path) -> Result<Self, TestContextError> {
+        Self::create(repo_dir, false, 0)
+    }
+
+    fn create(repo_dir: &Path, seeded: bool, borrowed: u64) -> Result<Self, TestContextError> {
+        let mut fixture = LendingFixture::new_default(repo_dir)?;
+        let program_id = fixture.program_id();
+        let victim = fixture.user;
+        let context = fixture.context_mut();
+        let attacker = context.create_funded_account(1_000_000_000);
+        let mint = context.create_mint(victim, 6);
+        let victim_ata = associated_token_address(&victim, &mint);
+
+        if !seeded {
+            context.create_token_account_at(victim_ata, victim, mint, VICTIM_DEPOSIT);
+            return Ok(Self { fixture, program_id, victim, attacker, mint });
+        }
+
+        fixture.seed_bank(&Bank {
+            authority: victim,
+            mint_address: mint,
+            total_deposits: VICTIM_DEPOSIT,
+            total_deposit_shares: VICTIM_DEPOSIT,
+            total_borrowed: borrowed,
+            total_borrowed_shares: borrowed,
+            liquidation_threshold: 8_000,
+            liquidation_bonus: 500,
+            liquidation_close_factor: 5_000,
+            max_ltv: 7_500,
+            ..Default::default()
+        })?;
+        fixture.seed_user(&User {
+            owner: victim,
+            deposited_usdc: VICTIM_DEPOSIT,
+            deposited_usdc_shares: VICTIM_DEPOSIT,
+            borrowed_usdc: borrowed,
+            borrowed_usdc_shares: borrowed,
+            usdc_address: mint,
+            ..Default::default()
+        })?;
+        fixture.seed_user(&User { owner: attacker, usdc_address: mint, ..Default::default() })?;
+
+        let treasury = treasury_address(&program_id, &mint);
+        let context = fixture.context_mut();
+        context.create_token_account_at(treasury, treasury, mint, VICTIM_DEPOSIT - borrowed);
+        context.create_token_account_at(victim_ata, victim, mint, VICTIM_DEPOSIT);
+
+        Ok(Self { fixture, program_id, victim, attacker, mint })
+    }
+
+    /// The current state of the seeded bank.
+    pub fn bank_state(&mut self) -> Bank {
+        let bank = bank_address(&self.program_id, &self.mint);
+        self.fixture
+            .context_mut()
+            .get_account(&bank)
+            .and_then(|account| Bank::decode(&account.data))
+            .unwrap_or_default()
+    }
+
+    /// The seeded bank's totals and the token balances of the victim and
+    /// the treasury.
+    fn balances(&mut self) -> Balances {
+        let bank = self.bank_state();
+        let victim_ata = associated_token_address(&self.victim, &self.mint);
+        let treasury = treasury_address(&self.program_id, &self.mint);
+        let context = self.fixture.context_mut();
+        let token_balance = |address| {
+            context
+                .get_account(&address)
+                .and_then(|account| TokenAccount::unpack(&account.data).ok())
+                .map_or(0, |account| account.amount)
+        };
+        Balances {
+            bank,
+            victim_tokens: token_balance(victim_ata),
+            treasury_tokens: token_balance(treasury),
+        }
+    }
+
+    /// Build `instruction` as `signer` would, resolving every account slot
+    /// from its IDL address, PDA seeds or name.
+    ///
+    /// # Arguments
+    ///
+    /// * `instruction` - The instruction whose accounts to resolve
+    /// * `args` - The integer arguments its data carries; others are placeholders
+    /// * `signer` - The account that signs
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<(String, AccountMeta)>)` - The account slots, by IDL name
+    /// * `Err(TestContextError)` - If a PDA has a seed that cannot be resolved
+    pub fn resolve_accounts(
+        &self,
+        instruction: &IdlInstruction,
+        args: &BTreeMap<String, u64>,
+        signer: Pubkey,
+    ) -> Result<Vec<(String, AccountMeta)>, TestContextError> {
+        resolve_accounts(instruction, &self.program_id, args, &HashMap::new(), |account| {
+            if account.signer {
+                signer
+            } else if account.name.contains("mint") {
+                self.mint
+            } else {
+                Pubkey::new_unique()
+            }
+        })
+        .map_err(TestContextError::ValidationError)
+    }
+
+    /// Create the account that replaces `original` under `attack`, or
+    /// `None` if the attack does not apply to the account in that slot.
+    fn substitute(&mut self, original: Pubkey, attack: Attack) -> Option<Pubkey> {
+        let program_id = self.program_id;
+        let context = self.fixture.context_mut();
+        let account = context.get_account(&original);
+
+        match attack {
+            Attack::MissingSigner => None,
+            Attack::SubstitutedAuthority => Some(self.attacker),
+            Attack::ForeignUserAccount => Some(user_address(&program_id, &self.victim)),
+            Attack::WrongAccountType => {
+                let data = account?.data;
+                if Bank::decode(&data).is_some() {
+                    Some(user_address(&program_id, &self.victim))
+                } else if User::decode(&data).is_some() {
+                    Some(bank_address(&program_id, &self.mint))
+                } else {
+                    None
+                }
+            }
+            Attack::MismatchedMint => {
+                let account = account.filter(|account| {
+                    account.owner == spl_token_interface::ID &&
+                        account.data.len() == TokenAccount::LEN
+                })?;
+                let owner = TokenAccount::unpack(&account.data).ok()?.owner;
+                let other_mint = context.create_mint(self.attacker, 6);
+                Some(context.create_token_account(owner, other_mint, VICTIM_DEPOSIT))
+            }
+            Attack::ForeignOwner => {
+                let mut account = account.filter(|account| account.owner == program_id)?;
+                account.owner = Pubkey::new_unique();
+                let pubkey = Pubkey::new_unique();
+                context.add_account(pubkey, account);
+                Some(pubkey)
+            }
+            Attack::FakeMint => {
+                account.filter(|account| {
+                    account.owner == spl_token_interface::ID && account.data.len() == Mint::LEN
+                })?;
+                Some(context.create_mint(self.attacker, 6))
+            }
+        }
+    }
+}
+
+/// Derive the associated token account of `wallet` for `mint`.
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token_interface::ID.as_ref(), mint.as_ref()],
+        &spl_associated_token_account_interface::program::ID,
+    )
+    .0
+}
+
+/// Whether an account slot holds the SPL Token program.
+pub fn is_token_program_slot(account: &IdlAccount) -> bool {
+    matches!(account.name.as_str(), "token_program" | "tokenProgram")
+}
+
+/// Whether an account slot holds a `User` account.
+pub fn is_user_slot(account: &IdlAccount) -> bool {
+    !account.signer && matches!(account.name.as_str(), "user" | "user_account" | "userAccount")
+}
+
+/// Run the missing-signer and wrong-authority attacks against every
+/// instruction in the IDL.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(())` - If every attack was rejected
+/// * `Err(TestContextError)` - A list of the attacks that succeeded
+pub fn check_authority_attacks(repo_dir: &Path) -> Result<(), TestContextError> {
+    run_catalogue(repo_dir, |account| {
+        if account.signer {
+            vec![Attack::MissingSigner, Attack::SubstitutedAuthority]
+        } else if is_user_slot(account) {
+            vec![Attack::ForeignUserAccount]
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+/// Run the account substitution attacks against every non-signer slot of
+/// every instruction in the IDL.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(())` - If every substitution was rejected
+/// * `Err(TestContextError)` - A list of the substitutions that succeeded
+pub fn check_account_substitution(repo_dir: &Path) -> Result<(), TestContextError> {
+    run_catalogue(repo_dir, |account| {
+        if account.signer {
+            Vec::new()
+        } else {
+            vec![
+                Attack::WrongAccountType,
+                Attack::MismatchedMint,
+                Attack::ForeignOwner,
+                Attack::FakeMint,
+            ]
+        }
+    })
+}
+
+/// What happened when an attack was run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttackOutcome {
+    /// The tampered instruction executed: a vulnerability.
+    Succeeded,
+    /// The program rejected the tampered instruction.
+    Rejected,
+    /// The attack does not apply to the account in the slot.
+    NotApplicable,
+}
+
+/// The state in which an instruction succeeds untampered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Baseline {
+    /// The instruction succeeds against [`AttackSetup::new`].
+    Seeded,
+    /// The instruction succeeds against [`AttackSetup::unseeded`].
+    Unseeded,
+    /// The instruction fails in both, with the seeded setup's error.
+    Failed(String),
+}
+
+impl Baseline {
+    /// A fresh setup of the kind the instruction succeeded in.
+    fn setup(&self, repo_dir: &Path) -> Result<AttackSetup, TestContextError> {
+        match self {
+            Baseline::Unseeded => AttackSetup::unseeded(repo_dir),
+            _ => AttackSetup::new(repo_dir),
+        }
+    }
+}
+
+/// Run `instruction` untampered, signed by the victim, to find a setup in
+/// which it succeeds.
+fn establish_baseline(
+    repo_dir: &Path,
+    instruction: &IdlInstruction,
+) -> Result<Baseline, TestContextError> {
+    // An instruction that cannot be encoded is a configuration error, not a
+    // baseline failure.
+    instruction.discriminator().map_err(TestContextError::ValidationError)?;
+    let run = |mut setup: AttackSetup| {
+        let accounts = setup.resolve_accounts(instruction, &BTreeMap::new(), setup.victim)?;
+        let ix = build_instruction(setup.program_id, instruction, accounts)?;
+        setup.fixture.context_mut().execute_instruction(&ix)
+    };
+
+    let Err(error) = run(AttackSetup::new(repo_dir)?) else {
+        return Ok(Baseline::Seeded);
+    };
+    if run(AttackSetup::unseeded(repo_dir)?).is_ok() {
+        return Ok(Baseline::Unseeded);
+    }
+    Ok(Baseline::Failed(error.to_string()))
+}
+
+/// Establish the baseline of an instruction about to be attacked.
+///
+/// # Returns
+///
+/// * `Ok(Some(Baseline))` - The setup its attacks run against
+/// * `Ok(None)` - If it fails untampered and is not in [`BASELINE_REQUIRED`]; this is logged
+/// * `Err(TestContextError)` - If it fails untampered and is required
+fn require_baseline(
+    repo_dir: &Path,
+    instruction: &IdlInstruction,
+) -> Result<Option<Baseline>, TestContextError> {
+    let baseline = establish_baseline(repo_dir, instruction)?;
+    let Baseline::Failed(error) = &baseline else {
+        return Ok(Some(baseline));
+    };
+    if BASELINE_REQUIRED.iter().any(|name| instruction.matches(name)) {
+        return Err(TestContextError::ValidationError(format!(
+            "could not establish a baseline: `{}` fails without any tampering: {}",
+            instruction.name, error
+        )));
+    }
+    report::log(format!(
+        "Skipping attacks on `{}`, which fails without any tampering: {}",
+        instruction.name, error
+    ));
+    Ok(None)
+}
+
+/// Run the attacks selected by `attacks_for` against each account slot of
+/// each instruction in the IDL and report the ones that succeeded.
+fn run_catalogue(
+    repo_dir: &Path,
+    attacks_for: impl Fn(&IdlAccount) -> Vec<Attack>,
+) -> Result<(), TestContextError> {
+    let idl = load_idl(repo_dir)?;
+    let mut findings = Vec::new();
+    let mut applied = 0;
+    let mut skipped = 0;
+
+    for instruction in &idl.instructions {
+        let accounts = instruction.accounts();
+        if accounts.iter().all(|account| attacks_for(account).is_empty()) {
+            continue;
+        }
+        let Some(baseline) = require_baseline(repo_dir, instruction)? else {
+            continue;
+        };
+
+        for (index, account) in accounts.into_iter().enumerate() {
+            for attack in attacks_for(account) {
+                match run_attack(repo_dir, &baseline, instruction, index, attack)? {
+                    AttackOutcome::Succeeded => {
+                        findings.push(finding(instruction, account, attack))
+                    }
+                    AttackOutcome::Rejected => {}
+                    AttackOutcome::NotApplicable => {
+                        skipped += 1;
+                        continue;
+                    }
+                }
+                applied += 1;
+            }
+        }
+    }
+
+    if skipped > 0 {
+        report::log(format!("Skipped {} attack(s) that do not apply to their account", skipped));
+    }
+    if applied == 0 {
+        return Err(TestContextError::ValidationError(
+            "could not establish a baseline: no attack applies to an instruction that succeeds \
+             without tampering"
+                .to_string(),
+        ));
+    }
+    report_findings(findings)
+}
+
+/// Pass the in-process attacker program in place of the SPL Token program
+/// to every instruction that takes one.
+///
+/// The attacker tries to call back into the lending program and reports
+/// success either way, so any instruction that completes with it has
+/// trusted an unverified token program. Each instruction is first run with
+/// the real token program, so that a rejection is due to the substitution.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(())` - If every instruction rejected the fake token program
+/// * `Err(TestContextError)` - The instructions that accepted it
+pub fn check_reentrancy(repo_dir: &Path) -> Result<(), TestContextError> {
+    let idl = load_idl(repo_dir)?;
+    let mut findings = Vec::new();
+    let mut tested = 0;
+
+    for instruction in &idl.instructions {
+        let Some(slot) =
+            instruction.accounts().iter().position(|account| is_token_program_slot(account))
+        else {
+            continue;
+        };
+        let Some(baseline) = require_baseline(repo_dir, instruction)? else {
+            continue;
+        };
+        tested += 1;
+
+        let mut setup = baseline.setup(repo_dir)?;
+        let mut accounts = setup.resolve_accounts(instruction, &BTreeMap::new(), setup.victim)?;
+        accounts[slot].1.pubkey = ATTACKER_PROGRAM_ID;
+        let ix = build_instruction(setup.program_id, instruction, accounts)?;
+
+        let context = setup.fixture.context_mut();
+        context.add_attacker_program();
+        let accepted = context.execute_instruction(&ix).is_ok();
+        let outcome = context.attacker_outcome();
+
+        if accepted {
+            let description = if outcome.reentered {
+                "accepted a fake token program, whose reentrant call succeeded"
+            } else if outcome.invoked {
+                "accepted a fake token program and invoked it"
+            } else {
+                "accepted a fake token program"
+            };
+            findings.push(Finding {
+                instruction: instruction.name.clone(),
+                account: instruction.accounts()[slot].name.clone(),
+                description: description.to_string(),
+            });
+        }
+    }
+
+    if tested == 0 {
+        return Err(TestContextError::ValidationError(
+            "could not establish a baseline: no instruction in the IDL that takes a token \
+             program account succeeds without tampering"
+                .to_string(),
+        ));
+    }
+
+    report_findings(findings)
+}
+
+/// Drive `deposit`, `borrow` and `repay` with zero, one, near-overflow and
+/// maximum amounts, against a victim who holds both a deposit and a loan.
+///
+/// A clean error return is always acceptable. A panic, or a success that
+/// leaves the bank's totals or the token balances of the victim and the
+/// treasury inconsistent with the amount, is reported; the two kinds are
+/// listed separately.
+///
+/// The victim's token account is full for `deposit` and `repay` and empty
+/// for `borrow`, so the SPL Token program never rejects a transfer for the
+/// victim's balance. The stage fails if no nonzero borrow either succeeds
+/// or is rejected by the lending program itself, since it then checked
+/// nothing.
+///
+/// # Arguments
+///
+/// * `repo_dir` - Path to the user's repository directory
+///
+/// # Returns
+///
+/// * `Ok(())` - If every extreme amount errored cleanly or was accounted for
+/// * `Err(TestContextError)` - The panics and inconsistencies found
+pub fn check_extreme_values(repo_dir: &Path) -> Result<(), TestContextError> {
+    let idl = load_idl(repo_dir)?;
+    // The smallest amount whose share calculation `amount * total_shares`
+    // overflows a u64 against the seeded bank.
+    let share_overflow = u64::MAX / VICTIM_DEPOSIT + 1;
+    let amounts = [0, 1, share_overflow, u64::MAX / 2 + 1, u64::MAX - 1, u64::MAX];
+
+    let mut panics = Vec::new();
+    let mut inconsistencies = Vec::new();
+
+    let mut setup = AttackSetup::with_borrow(repo_dir, VICTIM_BORROW)?;
+    let victim_ata = associated_token_address(&setup.victim, &setup.mint);
+    let mut fund_victim = |amount: u64| {
+        let context = setup.fixture.context_mut();
+        context.create_token_account_at(victim_ata, setup.victim, setup.mint, amount);
+        context.snapshot()
+    };
+    let funded = fund_victim(u64::MAX);
+    let emptied = fund_victim(0);
+    let (mut borrows_tried, mut borrows_reached) = (0, 0);
+
+    for name in ["deposit", "borrow", "repay"] {
+        let Some(instruction) = idl.instruction(name) else {
+            continue;
+        };
+        let Some(amount_arg) = instruction.args.iter().find(|arg| arg.ty == "u64") else {
+            continue;
+        };
+        let start = if name == "borrow" { emptied } else { funded };
+
+        for amount in amounts {
+            let args = BTreeMap::from([(amount_arg.name.clone(), amount)]);
+            let data =
+                instruction.data_with_args(&args).map_err(TestContextError::ValidationError)?;
+
+            setup.fixture.context_mut().restore(start)?;
+            let before = setup.balances();
+            let accounts = setup.resolve_accounts(instruction, &args, setup.victim)?;
+            let ix = create_lending_instruction(
+                setup.program_id,
+                data,
+                accounts.into_iter().map(|(_, meta)| meta).collect(),
+            );
+            let result = setup.fixture.context_mut().process_instruction(&ix);
+            if name == "borrow" && amount > 0 {
+                borrows_tried += 1;
+                if result.raw_result.is_ok() ||
+                    setup.fixture.context_mut().failing_program() == Some(setup.program_id)
+                {
+                    borrows_reached += 1;
+                }
+            }
+
+            match result.raw_result {
+                Err(InstructionError::ProgramFailedToComplete) => {
+                    panics.push(format!("  - `{}` with amount {}", name, amount));
+                }
+                Err(_) => {}
+                Ok(()) => {
+                    let after = setup.balances();
+                    let problems = check_balance_delta(name, amount, &before, &after);
+                    if !problems.is_empty() {
+                        inconsistencies.push(format!(
+                            "  - `{}` with amount {}: {}",
+                            name,
+                            amount,
+                            problems.join("; ")
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if borrows_tried > 0 && borrows_reached == 0 {
+        return Err(TestContextError::ValidationError(
+            "no nonzero borrow succeeded or was rejected by the lending program itself, so no \
+             extreme borrow could be checked; borrowing 1 token against the seeded position \
+             should succeed"
+                .to_string(),
+        ));
+    }
+
+    if panics.is_empty() && inconsistencies.is_empty() {
+        return Ok(());
+    }
+
+    let mut message = String::from("extreme amounts were not rejected cleanly");
+    if !panics.is_empty() {
+        message.push_str("\npanicked (ProgramFailedToComplete):\n");
+        message.push_str(&panics.join("\n"));
+    }
+    if !inconsistencies.is_empty() {
+        message.push_str("\nsucceeded with inconsistent balances:\n");
+        message.push_str(&inconsistencies.join("\n"));
+    }
+    Err(TestContextError::ValidationError(message))
+}
+
+/// The state an extreme amount may move.
+#[derive(Debug, Clone)]
+struct Balances {
+    bank: Bank,
+    victim_tokens: u64,
+    treasury_tokens: u64,
+}
+
+/// Check that a successful `deposit`, `borrow` or `repay` of `amount` moved
+/// the bank's totals by exactly that amount, without wrapping, and moved the
+/// same number of tokens between the victim and the treasury.
+///
+/// # Returns
+///
+/// * `Vec<String>` - A description of each inconsistency, empty if there are none
+fn check_balance_delta(
+    name: &str,
+    amount: u64,
+    before: &Balances,
+    after: &Balances,
+) -> Vec<String> {
+    let change = |before: u64, after: u64| i128::from(after) - i128::from(before);
+    let amount = i128::from(amount);
+    let (field, before_total, after_total) = match name {
+        "deposit" => ("total_deposits", before.bank.total_deposits, after.bank.total_deposits),
+        _ => ("total_borrowed", before.bank.total_borrowed, after.bank.total_borrowed),
+    };
+    let total_change = change(before_total, after_total);
+
+    let mut problems = Vec::new();
+    let consistent = match name {
+        // Repaying more than the debt may be capped, but never increase it.
+        "repay" => (-amount..=0).contains(&total_change),
+        _ => total_change == amount,
+    };
+    if !consistent {
+        problems.push(format!("{} went from {} to {}", field, before_total, after_total));
+    }
+
+    // Tokens that should have moved from the victim to the treasury.
+    let paid_in = match name {
+        "deposit" => amount,
+        "borrow" => -amount,
+        _ => -total_change,
+    };
+    let token_accounts = [
+        ("the victim's token account", before.victim_tokens, after.victim_tokens, -paid_in),
+        ("the treasury", before.treasury_tokens, after.treasury_tokens, paid_in),
+    ];
+    for (account, before_tokens, after_tokens, expected) in token_accounts {
+        if change(before_tokens, after_tokens) != expected {
+            problems.push(format!("{} went from {} to {}", account, before_tokens, after_tokens));
+        }
+    }
+    problems
+}
+
+/// Execute one attack on a fresh setup of the instruction's baseline kind.
+fn run_attack(
+    repo_dir: &Path,
+    baseline: &Baseline,
+    instruction: &IdlInstruction,
+    slot: usize,
+    attack: Attack,
+) -> Result<AttackOutcome, TestContextError> {
+    let mut setup = baseline.setup(repo_dir)?;
+
+    let signer = match attack {
+        Attack::ForeignUserAccount => setup.attacker,
+        _ => setup.victim,
+    };
+    let mut accounts = setup.resolve_accounts(instruction, &BTreeMap::new(), signer)?;
+    let meta = &mut accounts[slot].1;
+    if attack == Attack::MissingSigner {
+        meta.is_signer = false;
+    } else {
+        match setup.substitute(meta.pubkey, attack) {
+            Some(pubkey) => meta.pubkey = pubkey,
+            None => return Ok(AttackOutcome::NotApplicable),
+        }
+    }
+
+    let ix = build_instruction(setup.program_id, instruction, accounts)?;
+    Ok(match setup.fixture.context_mut().execute_instruction(&ix) {
+        Ok(()) => AttackOutcome::Succeeded,
+        Err(_) => AttackOutcome::Rejected,
+    })
+}
+
+/// Assemble an instruction from resolved account slots and placeholder
+/// arguments, failing if the instruction has no discriminator.
+pub fn build_instruction(
+    program_id: Pubkey,
+    instruction: &IdlInstruction,
+    accounts: Vec<(String, AccountMeta)>,
+) -> Result<Instruction, TestContextError> {
+    Ok(create_lending_instruction(
+        program_id,
+        instruction.placeholder_data().map_err(TestContextError::ValidationError)?,
+        accounts.into_iter().map(|(_, meta)| meta).collect(),
+    ))
+}
+
+fn finding(instruction: &IdlInstruction, account: &IdlAccount, attack: Attack) -> Finding {
+    Finding {
+        instruction: instruction.name.clone(),
+        account: account.name.clone(),
+        description: attack.to_string(),
+    }
+}
+
+/// Turn a list of findings into a validation error listing all of them.
+pub fn report_findings(findings: Vec<Finding>) -> Result<(), TestContextError> {
+    if findings.is_empty() {
+        return Ok(());
+    }
+
+    let lines: Vec<String> = findings.iter().map(|finding| format!("  - {}", finding)).collect();
+    Err(TestContextError::ValidationError(format!(
+        "{} attack(s) succeeded:\n{}",
+        findings.len(),
+        lines.join("\n")
+    )))
+}
@@ -13,5 +13,5 @@
 // limitations under the License.
 
 pub fn test_rate_models(_harness: &tester::Harness) -> Result<(), tester::CaseError> {
-    crate::helpers::run_interest_checks()
+    crate::helpers::run_rate_model_checks()
 }
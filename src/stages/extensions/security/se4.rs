@@ -13,5 +13,5 @@
 // limitations under the License.
 
 pub fn test_security_practice(_harness: &tester::Harness) -> Result<(), tester::CaseError> {
-    crate::helpers::run_security_checks()
+    crate::helpers::run_extreme_value_checks()
 }